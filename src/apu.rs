@@ -1,3 +1,5 @@
+use crate::region::Region;
+
 const PI: f64 = 3.141529;
 
 #[derive(Default)]
@@ -180,15 +182,114 @@ struct Channel {
     sweep: Sweeper,
 }
 
+// Timer periods in cpu cycles, Dendy uses the NTSC tables
+const DMC_RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const DMC_RATE_TABLE_PAL: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+// Noise sequencer reloads selected by $400E
+const NOISE_PERIOD_TABLE: [u16; 16] = [
+    0, 4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 1016, 2034, 4068,
+];
+const NOISE_PERIOD_TABLE_PAL: [u16; 16] = [
+    0, 4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 944, 1890, 3778,
+];
+
+// Frame sequencer steps in APU cycles, the 4th ends 4-step mode and the 5th 5-step mode
+const FRAME_STEPS: [u128; 5] = [3729, 7457, 11186, 14916, 18641];
+const FRAME_STEPS_PAL: [u128; 5] = [4157, 8314, 12470, 16628, 20783];
+
+// Length counter loads are the same on every region
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// Delta modulation channel, plays 1-bit deltas fetched from PRG by the DMA unit
+#[derive(Default)]
+struct Dmc {
+    irq_enable: bool,
+    irq: bool,
+    b_loop: bool,
+    timer: u16,
+    reload: u16,
+    output_level: u8,
+
+    sample_addr: u16,
+    sample_length: u16,
+    current_addr: u16,
+    bytes_remaining: u16,
+    buffer: u8,
+    buffer_full: bool,
+    dma_pending: bool,
+
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Dmc {
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // One cpu cycle
+    fn clock(&mut self) {
+        if self.timer > 0 {
+            self.timer = self.timer - 1;
+            return;
+        }
+        self.timer = self.reload;
+
+        if !self.silence {
+            if self.shift & 0x01 > 0 {
+                if self.output_level <= 125 {
+                    self.output_level = self.output_level + 2;
+                }
+            } else if self.output_level >= 2 {
+                self.output_level = self.output_level - 2;
+            }
+        }
+        self.shift = self.shift >> 1;
+
+        if self.bits_remaining > 0 {
+            self.bits_remaining = self.bits_remaining - 1;
+        }
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            if self.buffer_full {
+                self.silence = false;
+                self.shift = self.buffer;
+                self.buffer_full = false;
+            } else {
+                self.silence = true;
+            }
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct Apu {
     pulse1: Channel,
     pulse2: Channel,
     noise: Channel,
+    dmc: Dmc,
+    // The current cpu cycle is the first half of an APU cycle
+    get_cycle: bool,
     clock_counter: u128,
     frame_clock_counter: u128,
+    // $4017, 5-step mode never raises the frame IRQ
+    five_step: bool,
+    frame_irq_inhibit: bool,
+    frame_irq: bool,
+    // Writing $4017 in 5-step mode clocks the envelopes and counters at once
+    frame_clock_now: bool,
     global_time: f64,
-    length_table: [u8; 32],
+    region: Region,
 }
 
 impl Apu {
@@ -201,10 +302,12 @@ impl Apu {
                 },
                 ..Default::default()
             },
-            length_table: [
-                10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48,
-                20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
-            ],
+            dmc: Dmc {
+                reload: DMC_RATE_TABLE[0] - 1,
+                bits_remaining: 8,
+                silence: true,
+                ..Default::default()
+            },
             ..Default::default()
         };
     }
@@ -251,7 +354,7 @@ impl Apu {
                     (((data as u16) & 0x07) << 8) as u16 | (self.pulse1.seq.reload & 0x00FF) as u16;
                 self.pulse1.seq.timer = self.pulse1.seq.reload;
                 self.pulse1.seq.sequence = self.pulse1.seq.new_sequence;
-                self.pulse1.lc.counter = self.length_table[((data & 0xF8) >> 3) as usize];
+                self.pulse1.lc.counter = LENGTH_TABLE[((data & 0xF8) >> 3) as usize];
                 self.pulse1.env.start = true;
             }
             0x4004 => {
@@ -294,7 +397,7 @@ impl Apu {
                     (((data as u16) & 0x07) << 8) as u16 | (self.pulse2.seq.reload & 0x00FF) as u16;
                 self.pulse2.seq.timer = self.pulse2.seq.reload;
                 self.pulse2.seq.sequence = self.pulse2.seq.new_sequence;
-                self.pulse2.lc.counter = self.length_table[((data & 0xF8) >> 3) as usize];
+                self.pulse2.lc.counter = LENGTH_TABLE[((data & 0xF8) >> 3) as usize];
                 self.pulse2.env.start = true;
             }
             0x400C => {
@@ -302,103 +405,214 @@ impl Apu {
                 self.noise.env.disable = (data & 0x10) > 0;
                 self.noise.halt = (data & 0x20) > 0;
             }
-            0x400E => match data & 0x0F {
-                0x00 => {
-                    self.noise.seq.reload = 0;
-                }
-                0x01 => {
-                    self.noise.seq.reload = 4;
-                }
-                0x02 => {
-                    self.noise.seq.reload = 8;
-                }
-                0x03 => {
-                    self.noise.seq.reload = 16;
-                }
-                0x04 => {
-                    self.noise.seq.reload = 32;
-                }
-                0x05 => {
-                    self.noise.seq.reload = 64;
-                }
-                0x06 => {
-                    self.noise.seq.reload = 96;
-                }
-                0x07 => {
-                    self.noise.seq.reload = 128;
-                }
-                0x08 => {
-                    self.noise.seq.reload = 160;
-                }
-                0x09 => {
-                    self.noise.seq.reload = 202;
-                }
-                0x0A => {
-                    self.noise.seq.reload = 254;
-                }
-                0x0B => {
-                    self.noise.seq.reload = 380;
-                }
-                0x0C => {
-                    self.noise.seq.reload = 508;
-                }
-                0x0D => {
-                    self.noise.seq.reload = 1016;
-                }
-                0x0E => {
-                    self.noise.seq.reload = 2034;
+            0x400E => {
+                self.noise.seq.reload = self.noise_periods()[(data & 0x0F) as usize];
+            }
+            0x4010 => {
+                self.dmc.irq_enable = (data & 0x80) > 0;
+                if !self.dmc.irq_enable {
+                    self.dmc.irq = false;
                 }
-                0x0F => {
-                    self.noise.seq.reload = 4068;
+                self.dmc.b_loop = (data & 0x40) > 0;
+                self.dmc.reload = self.dmc_rates()[(data & 0x0F) as usize] - 1;
+            }
+            0x4011 => {
+                self.dmc.output_level = data & 0x7F;
+            }
+            0x4012 => {
+                self.dmc.sample_addr = 0xC000 | ((data as u16) << 6);
+            }
+            0x4013 => {
+                self.dmc.sample_length = ((data as u16) << 4) | 0x0001;
+            }
+            0x4017 => {
+                self.five_step = (data & 0x80) > 0;
+                self.frame_irq_inhibit = (data & 0x40) > 0;
+                if self.frame_irq_inhibit {
+                    self.frame_irq = false;
                 }
-                _ => {}
-            },
+                self.frame_clock_counter = 0;
+                self.frame_clock_now = self.five_step;
+            }
             0x4015 => {
                 self.pulse1.enable = (data & 0x01) > 0;
                 self.pulse2.enable = (data & 0x02) > 0;
                 self.noise.enable = (data & 0x04) > 0;
+                if (data & 0x10) > 0 {
+                    if self.dmc.bytes_remaining == 0 {
+                        self.dmc.restart();
+                    }
+                } else {
+                    self.dmc.bytes_remaining = 0;
+                }
+                self.dmc.irq = false;
             }
             0x400F => {
                 self.pulse1.env.start = true;
                 self.pulse2.env.start = true;
                 self.noise.env.start = true;
-                self.noise.lc.counter = self.length_table[((data & 0xF8) >> 3) as usize]
+                self.noise.lc.counter = LENGTH_TABLE[((data & 0xF8) >> 3) as usize]
             }
             _ => {}
         }
     }
-    pub fn cpu_read(&self, _: u8) -> u8 {
-        return 0;
+    pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        let data = self.peek(addr);
+        // Reading the status acknowledges the frame IRQ, the DMC one stays
+        if addr == 0x4015 {
+            self.frame_irq = false;
+        }
+        return data;
+    }
+
+    /// Status register without side effects, bits set for channels whose length counter is running
+    pub fn peek(&self, addr: u16) -> u8 {
+        let mut data = 0x00;
+        if addr == 0x4015 {
+            if self.pulse1.lc.counter > 0 {
+                data = data | 0x01;
+            }
+            if self.pulse2.lc.counter > 0 {
+                data = data | 0x02;
+            }
+            if self.noise.lc.counter > 0 {
+                data = data | 0x08;
+            }
+            if self.dmc.bytes_remaining > 0 {
+                data = data | 0x10;
+            }
+            if self.frame_irq {
+                data = data | 0x40;
+            }
+            if self.dmc.irq {
+                data = data | 0x80;
+            }
+        }
+        return data;
+    }
+
+    /// Address of the next sample byte once the DMC buffer runs empty, for the DMA unit.
+    /// Requests go out on put cycles so the fetch normally takes 4 cycles
+    pub fn take_dmc_request(&mut self) -> Option<u16> {
+        if self.get_cycle
+            || self.dmc.buffer_full
+            || self.dmc.bytes_remaining == 0
+            || self.dmc.dma_pending
+        {
+            return None;
+        }
+        self.dmc.dma_pending = true;
+        return Some(self.dmc.current_addr);
+    }
+
+    /// Sample byte fetched by the DMA unit
+    pub fn dmc_load(&mut self, data: u8) {
+        self.dmc.dma_pending = false;
+        self.dmc.buffer = data;
+        self.dmc.buffer_full = true;
+        if self.dmc.current_addr == 0xFFFF {
+            self.dmc.current_addr = 0x8000;
+        } else {
+            self.dmc.current_addr = self.dmc.current_addr + 1;
+        }
+
+        // $4015 may have stopped the sample while the fetch was in flight
+        if self.dmc.bytes_remaining > 0 {
+            self.dmc.bytes_remaining = self.dmc.bytes_remaining - 1;
+            if self.dmc.bytes_remaining == 0 {
+                if self.dmc.b_loop {
+                    self.dmc.restart();
+                } else if self.dmc.irq_enable {
+                    self.dmc.irq = true;
+                }
+            }
+        }
+    }
+
+    /// Frame counter or DMC interrupt waiting to be acknowledged
+    pub fn irq(&self) -> bool {
+        return self.frame_irq || self.dmc.irq;
     }
 
+    pub fn is_get_cycle(&self) -> bool {
+        return self.get_cycle;
+    }
+
+    // Periods already loaded keep their old value until the registers are written
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
+    fn noise_periods(&self) -> &'static [u16; 16] {
+        return match self.region {
+            Region::PAL => &NOISE_PERIOD_TABLE_PAL,
+            _ => &NOISE_PERIOD_TABLE,
+        };
+    }
+
+    fn dmc_rates(&self) -> &'static [u16; 16] {
+        return match self.region {
+            Region::PAL => &DMC_RATE_TABLE_PAL,
+            _ => &DMC_RATE_TABLE,
+        };
+    }
+
+    fn frame_steps(&self) -> &'static [u128; 5] {
+        return match self.region {
+            Region::PAL => &FRAME_STEPS_PAL,
+            _ => &FRAME_STEPS,
+        };
+    }
+
+    // One cpu cycle
     pub fn clock(&mut self) {
         let mut quarter_frame_clock = false;
         let mut half_frame_clock = false;
 
-        self.global_time += 0.3333333333 / 1789773.0;
+        let cpu_clock_rate = self.region.cpu_clock_rate();
+        self.global_time += 1.0 / cpu_clock_rate;
+
+        self.get_cycle = self.clock_counter % 2 == 0;
+        self.dmc.clock();
 
-        if self.clock_counter % 6 == 0 {
+        if self.clock_counter % 2 == 0 {
+            let steps = self.frame_steps();
             self.frame_clock_counter = self.frame_clock_counter + 1;
 
-            if self.frame_clock_counter == 3729 {
+            if self.frame_clock_counter == steps[0] {
                 quarter_frame_clock = true;
             }
 
-            if self.frame_clock_counter == 7457 {
+            if self.frame_clock_counter == steps[1] {
                 quarter_frame_clock = true;
                 half_frame_clock = true;
             }
 
-            if self.frame_clock_counter == 11186 {
+            if self.frame_clock_counter == steps[2] {
+                quarter_frame_clock = true;
+            }
+
+            if self.frame_clock_counter == steps[3] && !self.five_step {
                 quarter_frame_clock = true;
+                half_frame_clock = true;
+                self.frame_clock_counter = 0;
+                if !self.frame_irq_inhibit {
+                    self.frame_irq = true;
+                }
             }
 
-            if self.frame_clock_counter == 14916 {
+            if self.frame_clock_counter == steps[4] {
                 quarter_frame_clock = true;
                 half_frame_clock = true;
                 self.frame_clock_counter = 0;
             }
 
+            if self.frame_clock_now {
+                quarter_frame_clock = true;
+                half_frame_clock = true;
+                self.frame_clock_now = false;
+            }
+
             // Update functional units
 
             // Quater frame "beats" adjust the volume envelope
@@ -415,7 +629,8 @@ impl Apu {
                 *s = ((*s & (0x0001 as u32)) << 7) | ((*s & (0x00FE as u32)) >> 1);
             });
 
-            self.pulse1.osc.frequency = 1789773.0 / (16.0 * (self.pulse1.seq.reload + 1) as f64);
+            self.pulse1.osc.frequency =
+                cpu_clock_rate / (16.0 * (self.pulse1.seq.reload + 1) as f64);
             self.pulse1.osc.amplitude = (self.pulse1.env.output.wrapping_sub(1)) as f64 / 16.0;
             self.pulse1.sample = self.pulse1.osc.sample(self.global_time);
 
@@ -445,7 +660,8 @@ impl Apu {
                 *s = ((*s & (0x0001 as u32)) << 7) | ((*s & (0x00FE as u32)) >> 1);
             });
 
-            self.pulse2.osc.frequency = 1789773.0 / (16.0 * (self.pulse2.seq.reload + 1) as f64);
+            self.pulse2.osc.frequency =
+                cpu_clock_rate / (16.0 * (self.pulse2.seq.reload + 1) as f64);
             self.pulse2.osc.amplitude = (self.pulse2.env.output.wrapping_sub(1)) as f64 / 16.0;
             self.pulse2.sample = self.pulse2.osc.sample(self.global_time);
 
@@ -477,7 +693,7 @@ impl Apu {
                 *s = (((*s & 0x0001) ^ ((*s & 0x0002) >> 1)) << 14) | ((*s & 0x7FFF) >> 1);
             });
 
-            self.noise.osc.frequency = 1789773.0 / (16.0 * (self.noise.seq.reload + 1) as f64);
+            self.noise.osc.frequency = cpu_clock_rate / (16.0 * (self.noise.seq.reload + 1) as f64);
             self.noise.osc.amplitude = (self.noise.env.output.wrapping_sub(1)) as f64 / 16.0;
             self.noise.sample = self.noise.osc.sample(self.global_time);
 
@@ -504,7 +720,8 @@ impl Apu {
     pub fn get_output_sample(&self) -> f64 {
         return ((1.0 * self.pulse1.output) - 0.8) * 0.4
             + ((1.0 * self.pulse2.output) - 0.8) * 0.4
-            + ((2.0 * self.noise.output) - 0.5) * 0.2;
+            + ((2.0 * self.noise.output) - 0.5) * 0.2
+            + (self.dmc.output_level as f64 / 127.0) * 0.2;
     }
 
     // fn sample_square_wave(f: f32, t: f32) -> f32 {
@@ -1,13 +1,13 @@
 use crate::ppu::Ppu;
 use crate::cartridge::Cartridge;
 use crate::apu::Apu;
+use crate::cdl::{CDL_DATA, CDL_PCM};
+use crate::dma::{Dma, DmaCycle};
+use crate::debugger::Debugger;
+use crate::region::Region;
 use std::sync::Arc;
 use std::sync::Mutex;
 
-// For Logging:
-// use std::io::Write;
-// use crate::util::hex;
-
 pub struct Bus {
     ppu: Ppu,
     pub apu: Apu,
@@ -15,14 +15,11 @@ pub struct Bus {
     cart: Option<Arc<Mutex<Cartridge>>>,
     controller_state: [u8; 2],
     pub controller: [u8; 2],
+    // Last value on the cpu data bus, what reads nothing answers return
+    open_bus: u8,
 
-    // Foreground Sprites
-    pub dma_page: u8,
-    pub dma_addr: u8,
-    pub dma_data: u8,
-
-    pub dma_transfer: bool,
-    pub dma_dummy: bool,
+    // Sprite and DMC sample transfers, the cpu is halted while they run
+    pub dma: Dma,
 
     // For Audio
     pub audio_time: f64,
@@ -30,6 +27,10 @@ pub struct Bus {
     pub audio_time_per_clock: f64,
     pub audio_time_per_sample: f64,
     pub audio_sample: f64,
+
+    pub debugger: Debugger,
+    // Set while the cartridge keeps a code/data log, so fetches skip its lock otherwise
+    cdl: bool,
 }
 
 impl Bus {
@@ -41,77 +42,193 @@ impl Bus {
             cart: None,
             controller: [0; 2],
             controller_state: [0; 2],
-            dma_page: 0,
-            dma_addr: 0,
-            dma_data: 0,
-            dma_transfer: false,
-            dma_dummy: false,
+            open_bus: 0,
+            dma: Dma::new(),
 
             audio_time: 0.0,
             audio_global_time: 0.0,
             audio_time_per_clock: 0.0,
             audio_time_per_sample: 0.0,
             audio_sample: 0.0,
+
+            debugger: Debugger::new(),
+            cdl: false,
         };
         return b;
     }
 
     pub fn reset(&mut self) {
         println!("Bus Reset Start");
-        self.dma_page = 0x00;
-        self.dma_addr = 0x00;
-        self.dma_data = 0x00;
-        self.dma_dummy = true;
-        self.dma_transfer = false;
+        self.dma.reset();
         println!("Bus Reset End");
+        if let Some(cart) = &self.cart {
+            cart.lock().unwrap().reset();
+        }
         self.ppu.reset();
     }
 
     pub fn write(&mut self, addr: usize, data: u8) {
-        if self.cart.as_ref().unwrap().lock().unwrap().cpu_write(addr, data) {
+        self.debugger.on_write(addr as u16);
+        self.poke(addr as u16, data);
+    }
+
+    /// Write like the cpu but without tripping watchpoints, for debuggers
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        let addr = addr as usize;
+        self.open_bus = data;
+        let mapped = match &self.cart {
+            Some(cart) => cart.lock().unwrap().cpu_write(addr, data),
+            None => false,
+        };
+        if mapped {
         } else if addr <= 0x1FFF {
             self.cpu_ram[addr & 0x07FF] = data;
         } else if addr >= 0x2000 && addr <= 0x3FFF {
             self.ppu.cpu_write(addr & 0x0007, data);
-        } else if addr <= 0x4013 || addr == 0x4015 {
+        } else if addr <= 0x4013 || addr == 0x4015 || addr == 0x4017 {
             self.apu.cpu_write(addr as u16, data);
         } else if addr == 0x4014 {
-            self.dma_page = data;
-            self.dma_addr = 0x00;
-            self.dma_transfer = true;
-        } else if addr >= 0x4016 && addr <= 0x4017 {
-            self.controller_state[addr & 0x0001] = self.controller[addr & 0x0001];
+            self.dma.start_oam(data);
+        } else if addr == 0x4016 {
+            // The strobe latches both controller ports
+            self.controller_state[0] = self.controller[0];
+            self.controller_state[1] = self.controller[1];
         }
     }
     pub fn read(&mut self, addr: usize, rdonly: bool) -> u8 {
-        let mut data = 0;
-        if self.cart.as_ref().unwrap().lock().unwrap().cpu_read(addr, &mut data) {
+        if rdonly {
+            return self.peek(addr as u16);
+        }
+        self.debugger.on_read(addr as u16);
+
+        let mut data = self.open_bus;
+        let mapped = match &self.cart {
+            Some(cart) => cart.lock().unwrap().cpu_read(addr, &mut data),
+            None => false,
+        };
+        if mapped {
         } else if addr <= 0x1FFF {
             data = self.cpu_ram[addr & 0x07FF];
         } else if addr >= 0x2000 && addr <= 0x3FFF {
             data = self.ppu.cpu_read(addr & 0x0007, rdonly);
         } else if addr == 0x4015 {
-            data = self.apu.cpu_read(addr as u8);
+            // Read inside the cpu, bit 5 is the old bus value and the bus keeps it
+            return self.apu.cpu_read(addr as u16) | (self.open_bus & 0x20);
         } else if addr >= 0x4016 && addr <= 0x4017 {
+            // Only the low bits are driven by the controller port
+            data = self.open_bus & 0xE0;
             if (self.controller_state[addr & 0x0001] & 0x80) > 0 {
-                data = 1;
-            } else {
-                data = 0;
+                data = data | 1;
             }
             self.controller_state[addr & 0x0001] = self.controller_state[addr & 0x0001] << 1;
         }
 
+        self.open_bus = data;
+        return data;
+    }
+
+    /// True when a DMA wants the bus, the cpu should hand over its next read cycle
+    pub fn dma_pending(&mut self) -> bool {
+        if let Some(addr) = self.apu.take_dmc_request() {
+            self.dma.start_dmc(addr);
+        }
+        return self.dma.is_active();
+    }
+
+    /// Run one cpu cycle of DMA. The halt cycle repeats the read the cpu was about to do,
+    /// so $4016 and $2007 see an extra read, which is the well known controller glitch
+    pub fn dma_clock(&mut self, cpu_addr: u16) {
+        let get = self.apu.is_get_cycle();
+        match self.dma.clock(get) {
+            DmaCycle::HALT => {
+                self.read(cpu_addr as usize, false);
+            }
+            DmaCycle::IDLE => {}
+            DmaCycle::OAM_READ(addr) => {
+                let data = self.read(addr as usize, false);
+                self.cdl_log_prg(addr, CDL_DATA);
+                self.dma.oam_loaded(data);
+            }
+            DmaCycle::OAM_WRITE(addr, data) => {
+                self.ppu.set_oam(addr as usize, data);
+            }
+            DmaCycle::DMC_READ(addr) => {
+                let data = self.read(addr as usize, false);
+                self.cdl_log_prg(addr, CDL_PCM);
+                self.apu.dmc_load(data);
+            }
+        }
+    }
+
+    /// Mark the cartridge byte behind a cpu address in the code/data log
+    pub fn cdl_log_prg(&mut self, addr: u16, flags: u8) {
+        if !self.cdl {
+            return;
+        }
+        if let Some(cart) = &self.cart {
+            cart.lock().unwrap().cdl_log_prg(addr, flags);
+        }
+    }
+
+    /// Read without side effects on any device, for debuggers, cheats and checksums
+    pub fn peek(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        let mut data = self.open_bus;
+        if let Some(cart) = &self.cart {
+            if cart.lock().unwrap().cpu_read(addr, &mut data) {
+                return data;
+            }
+        }
+        if addr <= 0x1FFF {
+            data = self.cpu_ram[addr & 0x07FF];
+        } else if addr >= 0x2000 && addr <= 0x3FFF {
+            data = self.ppu.cpu_peek(addr & 0x0007);
+        } else if addr == 0x4015 {
+            data = self.apu.peek(addr as u16) | (self.open_bus & 0x20);
+        } else if addr >= 0x4016 && addr <= 0x4017 {
+            // Next bit the controller would shift out
+            data = (self.open_bus & 0xE0) | ((self.controller_state[addr & 0x0001] & 0x80) >> 7);
+        }
         return data;
     }
 
+    // Level triggered and wired-OR, held by any device on the bus until it is acknowledged
+    pub fn irq_line(&self) -> bool {
+        if self.apu.irq() {
+            return true;
+        }
+        match &self.cart {
+            Some(cart) => cart.lock().unwrap().irq_state(),
+            None => false,
+        }
+    }
+
     pub fn set_sample_frequency(&mut self, sample_rate: u32) {
         self.audio_time_per_sample = 1.0 / (sample_rate as f64);
-        self.audio_time_per_clock = 1.0 / 5369318.0; // PPU Clock Frequency
+        self.audio_time_per_clock = 1.0 / self.ppu.region().ppu_clock_rate(); // PPU Clock Frequency
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.ppu.set_region(region);
+        self.apu.set_region(region);
+        self.audio_time_per_clock = 1.0 / region.ppu_clock_rate();
     }
 
     pub fn insert_cartridge(&mut self, cart: Arc<Mutex<Cartridge>>) {
+        let logging = cart.lock().unwrap().cdl().is_some();
         self.cart = Some(Arc::clone(&cart));
         self.ppu.connect_cartridge(cart);
+        self.set_cdl(logging);
+    }
+
+    /// Tell the bus and the ppu whether the cartridge is keeping a code/data log
+    pub fn set_cdl(&mut self, enabled: bool) {
+        self.cdl = enabled;
+        self.ppu.set_cdl(enabled);
+    }
+
+    pub fn has_cartridge(&self) -> bool {
+        return self.cart.is_some();
     }
 
     pub fn get_ppu(&mut self) -> &mut Ppu {
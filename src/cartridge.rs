@@ -1,38 +1,35 @@
-use crate::mapper::Mapper;
-use crate::mapper_000::Mapper000;
+use crate::cdl::CodeDataLogger;
+use crate::mapper::{create_mapper, Mapper};
+use crate::rom_info::RomInfo;
 
-use byteorder::ReadBytesExt; // 1.2.7
-use std::{
-    fs::File,
-    io::{Read, Seek, SeekFrom},
-};
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
 
+#[derive(Copy, Clone, PartialEq)]
 pub enum Mirror {
+    HARDWARE,
     HORIZONTAL,
     VERTICAL,
-    // ONESCREENLO,
-    // ONESCREENHI,
-}
-
-#[derive(Default)]
-struct CartHeader {
-    name: [u8; 4],
-    prg_rom_chunks: u8,
-    chr_rom_chunks: u8,
-    mapper1: u8,
-    mapper2: u8,
-    _prg_ram_size: u8,
-    _tv_system1: u8,
-    _tv_system2: u8,
-    unused: [u8; 5],
+    ONESCREENLO,
+    ONESCREENHI,
 }
 
 // #[derive(Default)]
 pub struct Cartridge {
     pub v_prg_memory: Vec<u8>,
     v_chr_memory: Vec<u8>,
-    p_mapper: Mapper000,
-    pub mirror: Mirror,
+    chr_ram: bool,
+    pub v_prg_ram: Vec<u8>,
+    p_mapper: Box<dyn Mapper>,
+    hw_mirror: Mirror,
+    pub rom_info: RomInfo,
+
+    // Battery backed PRG RAM
+    save_path: Option<PathBuf>,
+    prg_ram_dirty: bool,
+
+    // Code/Data log of the PRG and CHR ROM, only kept while recording
+    cdl: Option<CodeDataLogger>,
 }
 
 impl Default for Mirror {
@@ -41,82 +38,244 @@ impl Default for Mirror {
     }
 }
 
+#[derive(Debug)]
+pub enum RomError {
+    NotFound(String),
+    Io(io::Error),
+    BadMagic,
+    TruncatedHeader,
+    TruncatedTrainer,
+    TruncatedPrg { expected: usize, found: usize },
+    TruncatedChr { expected: usize, found: usize },
+    NoPrgRom,
+    UnsupportedMapper(u16),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::NotFound(path) => write!(f, "ROM file not found: {}", path),
+            RomError::Io(err) => write!(f, "Could not read ROM: {}", err),
+            RomError::BadMagic => write!(f, "Not an iNES ROM (missing NES<EOF> signature)"),
+            RomError::TruncatedHeader => write!(f, "ROM is shorter than the 16 byte header"),
+            RomError::TruncatedTrainer => write!(f, "ROM ends inside the 512 byte trainer"),
+            RomError::TruncatedPrg { expected, found } => write!(
+                f,
+                "PRG ROM is truncated, expected {} bytes but found {}",
+                expected, found
+            ),
+            RomError::TruncatedChr { expected, found } => write!(
+                f,
+                "CHR ROM is truncated, expected {} bytes but found {}",
+                expected, found
+            ),
+            RomError::NoPrgRom => write!(f, "ROM header declares no PRG ROM"),
+            RomError::UnsupportedMapper(id) => write!(f, "Mapper {} is not supported", id),
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
 impl Cartridge {
-    pub fn new(file_name: &str) -> Self {
-        let mut header: CartHeader = Default::default();
-
-        let mut file = match File::open(file_name) {
-            Ok(f) => f,
-            Err(_) => {
-                return Cartridge {
-                    v_prg_memory: vec![],
-                    v_chr_memory: vec![],
-                    p_mapper: Mapper000::new(0, 0),
-                    mirror: Mirror::default(),
-                };
+    pub fn load(file_name: &str) -> Result<Cartridge, RomError> {
+        let data = match fs::read(file_name) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(RomError::NotFound(file_name.to_owned()));
             }
+            Err(err) => return Err(RomError::Io(err)),
         };
-        let _ = file.read_exact(&mut header.name);
-        header.prg_rom_chunks = file.read_u8().unwrap();
-        header.chr_rom_chunks = file.read_u8().unwrap();
-        header.mapper1 = file.read_u8().unwrap();
-        header.mapper2 = file.read_u8().unwrap();
-        header._prg_ram_size = file.read_u8().unwrap();
-        header._tv_system1 = file.read_u8().unwrap();
-        header._tv_system2 = file.read_u8().unwrap();
-        let _ = file.read_exact(&mut header.unused);
-
-        let mut mirror = Mirror::HORIZONTAL;
-        if header.mapper1 & 0x01 > 0 {
-            mirror = Mirror::VERTICAL
-        }
-
-        if header.mapper1 & 0x04 > 0 {
-            let _ = file.seek(SeekFrom::Current(512));
-        }
-
-        let mapper_id = ((header.mapper2 >> 4) << 4) | (header.mapper1 >> 4);
-        let file_type = 1;
-        let mut prg_banks = 0;
-        let mut v_prg_memory: Vec<u8> = Vec::new();
-        let mut chr_banks = 0;
-        let mut v_chr_memory: Vec<u8> = Vec::new();
-
-        match file_type {
-            0 => {}
-            1 => {
-                prg_banks = header.prg_rom_chunks;
-                v_prg_memory.resize((prg_banks as u32 * 16384) as usize, 0);
-                file.read_exact(&mut v_prg_memory).unwrap();
-
-                chr_banks = header.chr_rom_chunks;
-                v_chr_memory.resize((chr_banks as u32 * 8192) as usize, 0);
-                file.read_exact(&mut v_chr_memory).unwrap();
+        let mut cart = Cartridge::from_bytes(&data)?;
+        if cart.rom_info.battery {
+            let save_path = Path::new(file_name).with_extension("sav");
+            if let Err(err) = cart.load_battery_ram(&save_path) {
+                eprintln!("Could not load {}: {}", save_path.display(), err);
             }
-            2 => {}
-            3 => {}
-            _ => {}
+            cart.save_path = Some(save_path);
         }
+        return Ok(cart);
+    }
 
-        let mut p_mapper = Mapper000::new(prg_banks, chr_banks);
+    pub fn from_bytes(data: &[u8]) -> Result<Cartridge, RomError> {
+        if data.len() < 16 {
+            return Err(RomError::TruncatedHeader);
+        }
+        if &data[0..4] != b"NES\x1A" {
+            return Err(RomError::BadMagic);
+        }
 
-        match mapper_id {
-            0 => {
-                p_mapper = Mapper000::new(prg_banks, chr_banks);
+        let mut header = [0u8; 16];
+        header.copy_from_slice(&data[0..16]);
+        let rom_info = RomInfo::parse(&header);
+
+        let mut offset = 16;
+        if rom_info.trainer {
+            offset += 512;
+            if data.len() < offset {
+                return Err(RomError::TruncatedTrainer);
             }
-            _ => {}
         }
 
-        return Cartridge {
+        if rom_info.prg_rom_size == 0 {
+            return Err(RomError::NoPrgRom);
+        }
+        if data.len() < offset + rom_info.prg_rom_size {
+            return Err(RomError::TruncatedPrg {
+                expected: rom_info.prg_rom_size,
+                found: data.len() - offset,
+            });
+        }
+        let v_prg_memory = data[offset..offset + rom_info.prg_rom_size].to_vec();
+        offset += rom_info.prg_rom_size;
+
+        if data.len() < offset + rom_info.chr_rom_size {
+            return Err(RomError::TruncatedChr {
+                expected: rom_info.chr_rom_size,
+                found: data.len() - offset,
+            });
+        }
+        let chr_ram = rom_info.chr_rom_size == 0;
+        let v_chr_memory = if chr_ram {
+            // Carts without CHR ROM get at least 8K of CHR RAM
+            let size = rom_info.chr_ram_size + rom_info.chr_nvram_size;
+            vec![0; size.max(8 * 1024)]
+        } else {
+            data[offset..offset + rom_info.chr_rom_size].to_vec()
+        };
+
+        let p_mapper = match create_mapper(&rom_info) {
+            Some(mapper) => mapper,
+            None => return Err(RomError::UnsupportedMapper(rom_info.mapper_id)),
+        };
+
+        let v_prg_ram = vec![0; rom_info.prg_ram_size + rom_info.prg_nvram_size];
+
+        return Ok(Cartridge {
             v_prg_memory,
             v_chr_memory,
+            chr_ram,
+            v_prg_ram,
             p_mapper,
-            mirror,
+            hw_mirror: rom_info.mirror,
+            rom_info,
+            save_path: None,
+            prg_ram_dirty: false,
+            cdl: None,
+        });
+    }
+
+    // PRG RAM is the volatile RAM followed by the battery backed RAM
+    fn battery_ram(&mut self) -> &mut [u8] {
+        let start = self.rom_info.prg_ram_size.min(self.v_prg_ram.len());
+        return &mut self.v_prg_ram[start..];
+    }
+
+    /// Fill battery backed PRG RAM from a .sav file, a missing file leaves it cleared
+    pub fn load_battery_ram(&mut self, path: &Path) -> io::Result<()> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
         };
+        let battery_ram = self.battery_ram();
+        let len = data.len().min(battery_ram.len());
+        battery_ram[..len].copy_from_slice(&data[..len]);
+        self.prg_ram_dirty = false;
+        return Ok(());
+    }
+
+    /// Write battery backed PRG RAM to the .sav file next to the ROM if it changed since the last save
+    pub fn save_battery_ram(&mut self) -> io::Result<()> {
+        if !self.prg_ram_dirty {
+            return Ok(());
+        }
+        if let Some(path) = self.save_path.clone() {
+            fs::write(path, self.battery_ram())?;
+        }
+        self.prg_ram_dirty = false;
+        return Ok(());
     }
+
+    /// Start recording a code/data log, carrying on from an existing .cdl file if there is one
+    pub fn start_cdl(&mut self, path: Option<&Path>) -> io::Result<()> {
+        let prg_size = self.v_prg_memory.len();
+        let chr_size = self.rom_info.chr_rom_size;
+        self.cdl = match path {
+            Some(path) if path.exists() => Some(CodeDataLogger::load(path, prg_size, chr_size)?),
+            _ => Some(CodeDataLogger::new(prg_size, chr_size)),
+        };
+        return Ok(());
+    }
+
+    pub fn stop_cdl(&mut self) {
+        self.cdl = None;
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLogger> {
+        return self.cdl.as_ref();
+    }
+
+    /// Flag the PRG ROM byte currently mapped at a cpu address
+    pub fn cdl_log_prg(&mut self, addr: u16, flags: u8) {
+        if self.cdl.is_none() || addr < 0x8000 {
+            return;
+        }
+        let mut mapped_addr = 0 as u32;
+        if self.p_mapper.cpu_map_read(addr, &mut mapped_addr) {
+            if let Some(cdl) = &mut self.cdl {
+                cdl.log_prg(mapped_addr as usize, addr, flags);
+            }
+        }
+    }
+
+    /// Flag the CHR ROM byte currently mapped at a ppu address, CHR RAM is not logged
+    pub fn cdl_log_chr(&mut self, addr: u16, flags: u8) {
+        if self.cdl.is_none() || self.chr_ram || addr > 0x1FFF {
+            return;
+        }
+        let mut mapped_addr = 0 as u32;
+        if self.p_mapper.ppu_map_read(addr, &mut mapped_addr) {
+            let len = self.v_chr_memory.len();
+            if let Some(cdl) = &mut self.cdl {
+                cdl.log_chr(mapped_addr as usize % len, flags);
+            }
+        }
+    }
+
+    /// Current nametable mirroring, mappers may override the header
+    pub fn mirror(&self) -> Mirror {
+        match self.p_mapper.mirror() {
+            Mirror::HARDWARE => self.hw_mirror,
+            m => m,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.p_mapper.reset();
+    }
+
+    pub fn irq_state(&self) -> bool {
+        return self.p_mapper.irq_state();
+    }
+
+    pub fn ppu_a12_rise(&mut self) {
+        self.p_mapper.ppu_a12_rise();
+    }
+
+    pub fn cpu_clock(&mut self) {
+        self.p_mapper.cpu_clock();
+    }
+
     // Communications with cpu bus
     pub fn cpu_read(&self, addr: usize, data: &mut u8) -> bool {
         let mut mapped_addr = 0 as u32;
+        if addr >= 0x6000 && addr <= 0x7FFF && !self.v_prg_ram.is_empty() {
+            if self.p_mapper.prg_ram_map_read(addr as u16, &mut mapped_addr) {
+                *data = self.v_prg_ram[mapped_addr as usize % self.v_prg_ram.len()];
+                return true;
+            }
+        }
         if (self.p_mapper).cpu_map_read(addr as u16, &mut mapped_addr) {
             *data = self.v_prg_memory[mapped_addr as usize];
             return true;
@@ -125,7 +284,15 @@ impl Cartridge {
     }
     pub fn cpu_write(&mut self, addr: usize, data: u8) -> bool {
         let mut mapped_addr = 0 as u32;
-        if (self.p_mapper).cpu_map_write(addr as u16, &mut mapped_addr) {
+        if addr >= 0x6000 && addr <= 0x7FFF && !self.v_prg_ram.is_empty() {
+            if self.p_mapper.prg_ram_map_write(addr as u16, &mut mapped_addr) {
+                let len = self.v_prg_ram.len();
+                self.v_prg_ram[mapped_addr as usize % len] = data;
+                self.prg_ram_dirty = true;
+                return true;
+            }
+        }
+        if (self.p_mapper).cpu_map_write(addr as u16, &mut mapped_addr, data) {
             self.v_prg_memory[mapped_addr as usize] = data;
             return true;
         }
@@ -136,7 +303,7 @@ impl Cartridge {
     pub fn ppu_read(&self, addr: u16, data: &mut u8) -> bool {
         let mut mapped_addr = 0 as u32;
         if self.p_mapper.ppu_map_read(addr, &mut mapped_addr) {
-            *data = self.v_chr_memory[mapped_addr as usize];
+            *data = self.v_chr_memory[mapped_addr as usize % self.v_chr_memory.len()];
             return true;
         }
         return false;
@@ -144,7 +311,14 @@ impl Cartridge {
     pub fn ppu_write(&mut self, addr: usize, data: u8) -> bool {
         let mut mapped_addr = 0 as u32;
         if self.p_mapper.ppu_map_write(addr as u16, &mut mapped_addr) {
-            self.v_chr_memory[mapped_addr as usize] = data;
+            if self.chr_ram {
+                let len = self.v_chr_memory.len();
+                self.v_chr_memory[mapped_addr as usize % len] = data;
+            }
+            return true;
+        }
+        if addr <= 0x1FFF && !self.chr_ram {
+            // CHR ROM is read only, swallow the write
             return true;
         }
         return false;
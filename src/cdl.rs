@@ -0,0 +1,82 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// PRG byte flags, as used by FCEUX
+pub const CDL_CODE: u8 = 0x01;
+pub const CDL_DATA: u8 = 0x02;
+pub const CDL_INDIRECT_CODE: u8 = 0x10;
+pub const CDL_INDIRECT_DATA: u8 = 0x20;
+pub const CDL_PCM: u8 = 0x40;
+
+// CHR byte flags
+pub const CDL_CHR_RENDERED: u8 = 0x01;
+pub const CDL_CHR_READ: u8 = 0x02;
+
+// One flag byte per PRG ROM byte followed by one per CHR ROM byte, bits 2-3
+// of a PRG byte hold the 8K cpu window ($8000/$A000/$C000/$E000) it was seen in
+pub struct CodeDataLogger {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+}
+
+impl CodeDataLogger {
+    pub fn new(prg_size: usize, chr_size: usize) -> CodeDataLogger {
+        return CodeDataLogger {
+            prg: vec![0; prg_size],
+            chr: vec![0; chr_size],
+        };
+    }
+
+    /// Load a .cdl file, its size has to match the ROM it was recorded on
+    pub fn load(path: &Path, prg_size: usize, chr_size: usize) -> io::Result<CodeDataLogger> {
+        let data = fs::read(path)?;
+        if data.len() != prg_size + chr_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "expected {} bytes for this ROM but the file has {}",
+                    prg_size + chr_size,
+                    data.len()
+                ),
+            ));
+        }
+        return Ok(CodeDataLogger {
+            prg: data[..prg_size].to_vec(),
+            chr: data[prg_size..].to_vec(),
+        });
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut data = self.prg.clone();
+        data.extend_from_slice(&self.chr);
+        return fs::write(path, data);
+    }
+
+    pub fn clear(&mut self) {
+        self.prg.iter_mut().for_each(|b| *b = 0);
+        self.chr.iter_mut().for_each(|b| *b = 0);
+    }
+
+    pub fn log_prg(&mut self, offset: usize, addr: u16, flags: u8) {
+        if offset < self.prg.len() {
+            let bank = (((addr >> 13) & 0x03) as u8) << 2;
+            // The window bits are replaced, a byte reached through another window keeps the latest
+            self.prg[offset] = (self.prg[offset] & !0x0C) | flags | bank;
+        }
+    }
+
+    pub fn log_chr(&mut self, offset: usize, flags: u8) {
+        if offset < self.chr.len() {
+            self.chr[offset] = self.chr[offset] | flags;
+        }
+    }
+
+    /// Bytes logged as code, as data and PRG bytes never touched
+    pub fn prg_stats(&self) -> (usize, usize, usize) {
+        let code = self.prg.iter().filter(|b| **b & CDL_CODE > 0).count();
+        let data = self.prg.iter().filter(|b| **b & CDL_DATA > 0).count();
+        let unused = self.prg.iter().filter(|b| **b & (CDL_CODE | CDL_DATA) == 0).count();
+        return (code, data, unused);
+    }
+}
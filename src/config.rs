@@ -0,0 +1,52 @@
+use crate::palette::BUILTIN_PALETTES;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+
+// Kept in the working directory, next to where the emulator is started from
+const CONFIG_PATH: &str = "rust_nes.json";
+
+// Region setting that follows the cartridge header
+pub const REGION_AUTO: &str = "Auto";
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Name of a built in palette or the path of a .pal file
+    pub palette: String,
+    // REGION_AUTO or the name of a Region
+    pub region: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            palette: BUILTIN_PALETTES[0].to_owned(),
+            region: REGION_AUTO.to_owned(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load() -> Config {
+        match fs::read_to_string(CONFIG_PATH) {
+            Ok(text) => match serde_json::from_str(&text) {
+                Ok(config) => return config,
+                Err(err) => eprintln!("Could not parse {}: {}", CONFIG_PATH, err),
+            },
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    eprintln!("Could not read {}: {}", CONFIG_PATH, err);
+                }
+            }
+        }
+        return Config::default();
+    }
+
+    pub fn save(&self) {
+        let text = serde_json::to_string_pretty(self).unwrap();
+        if let Err(err) = fs::write(CONFIG_PATH, text) {
+            eprintln!("Could not save {}: {}", CONFIG_PATH, err);
+        }
+    }
+}
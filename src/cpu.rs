@@ -1,4 +1,7 @@
 use crate::bus::*;
+use crate::cdl::{CDL_CODE, CDL_DATA, CDL_INDIRECT_CODE, CDL_INDIRECT_DATA};
+use std::collections::BTreeMap;
+use crate::tracer::Tracer;
 
 pub enum FLAGS6502 {
     C = (1 << 0), // Carry Bit
@@ -11,11 +14,29 @@ pub enum FLAGS6502 {
     N = (1 << 7), // Negative
 }
 
+// How an instruction uses the bus once its addressing mode has found the operand
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Access {
+    READ,
+    WRITE,
+    RMW,
+    // Branches, jumps, stack and interrupt instructions drive every cycle themselves
+    CONTROL,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Interrupt {
+    NONE,
+    IRQ,
+    NMI,
+}
+
 pub struct Instruction {
     pub name: String,
     pub operate: fn(&mut Cpu) -> u8,
     pub addrmode: fn(&mut Cpu) -> u8,
     pub cycles: u8,
+    pub access: Access,
 }
 
 impl Instruction {
@@ -25,15 +46,32 @@ impl Instruction {
         addrmode: fn(&mut Cpu) -> u8,
         cycles: u8,
     ) -> Instruction {
+        let access = match name.trim_start_matches('*') {
+            "STA" | "STX" | "STY" | "SAX" => Access::WRITE,
+            "ASL" | "LSR" | "ROL" | "ROR" | "INC" | "DEC" | "SLO" | "RLA" | "SRE" | "RRA"
+            | "DCP" | "ISB" => Access::RMW,
+            "BRK" | "JSR" | "RTI" | "RTS" | "JMP" | "PHA" | "PHP" | "PLA" | "PLP" | "BCC"
+            | "BCS" | "BEQ" | "BMI" | "BNE" | "BPL" | "BVC" | "BVS" => Access::CONTROL,
+            _ => Access::READ,
+        };
         return Instruction {
             name: name.to_owned(),
             operate: operate,
             addrmode: addrmode,
             cycles: cycles,
+            access: access,
         };
     }
 }
 
+// One decoded instruction. Unofficial opcodes keep the '*' in front of their name
+pub struct Disassembly {
+    pub bytes: Vec<u8>,
+    pub name: String,
+    pub operand: String,
+    pub mode: &'static str,
+}
+
 pub struct Cpu {
     pub bus: Bus,
     pub status: u8,
@@ -46,8 +84,24 @@ pub struct Cpu {
     pub addr_abs: u16,
     pub addr_rel: u16,
     pub opcode: u8,
-    pub cycles: u8,
     pub lookup: Vec<Instruction>,
+
+    // Cycle of the current instruction that just ran, 0 between instructions.
+    // addr_step is the cycle the effective address was known on, 0 before that
+    pub step: u8,
+    pub addr_step: u8,
+    // Pointer operand of the indirect addressing modes
+    pub addr_ptr: u16,
+    pub interrupt: Interrupt,
+    pub nmi_pending: bool,
+    // Interrupt lines as sampled at the end of the previous cycle. The value
+    // left after an instruction's second to last cycle decides what runs next
+    pub nmi_poll: bool,
+    pub irq_poll: bool,
+
+    // Total cpu clocks since reset, and an optional nestest style instruction log
+    pub clock_count: u64,
+    pub tracer: Option<Tracer>,
 }
 
 #[allow(non_snake_case)]
@@ -64,38 +118,51 @@ impl Cpu {
         // return 0;
     }
 
+    // Addressing modes run one cycle per call from the second cycle of an
+    // instruction and return 1 on the cycle the effective address is known
+
     /// Addressing Mode : Implied
     pub fn IMP(&mut self) -> u8 {
+        // The byte after the opcode is read and ignored
+        self.read(self.pc, false);
         self.fetched = self.a;
-        return 0;
+        return 1;
     }
 
     /// Addressing Mode : Immediate
     pub fn IMM(&mut self) -> u8 {
         self.addr_abs = self.pc;
-        self.pc = self.pc + 1;
-        return 0;
+        self.pc = self.pc.wrapping_add(1);
+        return 1;
     }
 
     /// Addressing Mode: Zero Page
     pub fn ZP0(&mut self) -> u8 {
-        self.addr_abs = self.pcread();
-        self.addr_abs = self.addr_abs & 0x00FF;
-        return 0;
+        self.addr_abs = self.pcread() & 0x00FF;
+        return 1;
     }
 
     /// Addressing Mode: Zero Page with X Offset
     pub fn ZPX(&mut self) -> u8 {
-        self.addr_abs = self.pcread() + (self.x as u16);
-        self.addr_abs = self.addr_abs & 0x00FF;
-        return 0;
+        if self.step == 2 {
+            self.addr_abs = self.pcread();
+            return 0;
+        }
+        // The unindexed address is read while X is added
+        self.read(self.addr_abs, false);
+        self.addr_abs = (self.addr_abs + (self.x as u16)) & 0x00FF;
+        return 1;
     }
 
     /// Addressing Mode: Zero Page with Y Offset
     pub fn ZPY(&mut self) -> u8 {
-        self.addr_abs = self.pcread() + (self.y as u16);
-        self.addr_abs = self.addr_abs & 0x00FF;
-        return 0;
+        if self.step == 2 {
+            self.addr_abs = self.pcread();
+            return 0;
+        }
+        self.read(self.addr_abs, false);
+        self.addr_abs = (self.addr_abs + (self.y as u16)) & 0x00FF;
+        return 1;
     }
 
     /// Addressing Mode: Relative
@@ -104,112 +171,154 @@ impl Cpu {
         if self.addr_rel & 0x80 > 0 {
             self.addr_rel = self.addr_rel | 0xFF00
         }
-        return 0;
+        return 1;
     }
 
     /// Addressing Mode: Absolute
     pub fn ABS(&mut self) -> u8 {
-        let lo = self.pcread();
+        if self.step == 2 {
+            self.addr_abs = self.pcread();
+            return 0;
+        }
         let hi = self.pcread();
-
-        self.addr_abs = (hi << 8) | lo;
-
-        return 0;
+        self.addr_abs = (hi << 8) | self.addr_abs;
+        return 1;
     }
 
     /// Addressing Mode: Absolute with X offset
     pub fn ABX(&mut self) -> u8 {
-        let lo = self.pcread();
-        let hi = self.pcread();
-
-        self.addr_abs = (hi << 8) | lo;
-        self.addr_abs = self.addr_abs.wrapping_add(self.x as u16);
-        if (self.addr_abs & 0xFF00) != (hi << 8) {
-            return 1;
-        } else {
-            return 0;
+        match self.step {
+            2 => {
+                self.addr_abs = self.pcread();
+                return 0;
+            }
+            3 => {
+                let hi = self.pcread();
+                return self.index_low_byte((hi << 8) | self.addr_abs, self.x);
+            }
+            _ => {
+                return self.index_high_byte(self.x);
+            }
         }
     }
 
     /// Addressing Mode: Absolute with Y offset
     pub fn ABY(&mut self) -> u8 {
-        let lo = self.pcread();
-        let hi = self.pcread();
-
-        self.addr_abs = (hi << 8) | lo;
-        self.addr_abs = self.addr_abs.wrapping_add(self.y as u16);
-        if (self.addr_abs & 0xFF00) != (hi << 8) {
-            return 1;
-        } else {
-            return 0;
+        match self.step {
+            2 => {
+                self.addr_abs = self.pcread();
+                return 0;
+            }
+            3 => {
+                let hi = self.pcread();
+                return self.index_low_byte((hi << 8) | self.addr_abs, self.y);
+            }
+            _ => {
+                return self.index_high_byte(self.y);
+            }
         }
     }
 
     /// Addressing Mode: Indirect
     pub fn IND(&mut self) -> u8 {
-        let ptr_lo = self.pcread();
-        let ptr_hi = self.pcread();
-
-        let ptr: u16 = (ptr_hi << 8) | ptr_lo;
-
-        if ptr_lo == 0x00FF {
-            let hi_addr: u16 = self.read(ptr & 0xFF00, false).into();
-            let lo_addr: u16 = self.read(ptr, false).into();
-            self.addr_abs = (hi_addr << 8) | lo_addr;
-        } else {
-            let hi_addr: u16 = self.read(ptr + 1, false).into();
-            let lo_addr: u16 = self.read(ptr, false).into();
-            self.addr_abs = (hi_addr << 8) | lo_addr;
+        match self.step {
+            2 => {
+                self.addr_ptr = self.pcread();
+                return 0;
+            }
+            3 => {
+                let ptr_hi = self.pcread();
+                self.addr_ptr = (ptr_hi << 8) | self.addr_ptr;
+                return 0;
+            }
+            4 => {
+                self.addr_abs = self.read(self.addr_ptr, false).into();
+                self.bus.cdl_log_prg(self.addr_ptr, CDL_DATA);
+                return 0;
+            }
+            _ => {
+                // The pointer does not carry into its high byte, $xxFF wraps to $xx00
+                let hi_ptr = (self.addr_ptr & 0xFF00) | (self.addr_ptr.wrapping_add(1) & 0x00FF);
+                let hi_addr: u16 = self.read(hi_ptr, false).into();
+                self.bus.cdl_log_prg(hi_ptr, CDL_DATA);
+                self.addr_abs = (hi_addr << 8) | self.addr_abs;
+                return 1;
+            }
         }
-
-        return 0;
     }
 
     /// Addressing Mode: Indirect x offset
     pub fn IZX(&mut self) -> u8 {
-        let t = self.pcread();
-
-        let ptr = t + (self.x as u16);
-
-        let lo: u16 = self.read(ptr & 0x00FF, false).into();
-        let hi: u16 = self.read((ptr + 1) & 0x00FF, false).into();
-
-        self.addr_abs = (hi << 8) | lo;
-
-        return 0;
+        match self.step {
+            2 => {
+                self.addr_ptr = self.pcread();
+                return 0;
+            }
+            3 => {
+                self.read(self.addr_ptr, false);
+                self.addr_ptr = (self.addr_ptr + (self.x as u16)) & 0x00FF;
+                return 0;
+            }
+            4 => {
+                self.addr_abs = self.read(self.addr_ptr, false).into();
+                return 0;
+            }
+            _ => {
+                let hi: u16 = self.read((self.addr_ptr + 1) & 0x00FF, false).into();
+                self.addr_abs = (hi << 8) | self.addr_abs;
+                return 1;
+            }
+        }
     }
 
     /// Addressing Mode: Indirect Y offset
     pub fn IZY(&mut self) -> u8 {
-        let t = self.pcread();
-
-        let lo: u16 = self.read(t & 0x00FF, false).into();
-        let hi: u16 = self.read((t + 1) & 0x00FF, false).into();
-        self.addr_abs = (hi << 8) | lo;
-        self.addr_abs = self.addr_abs.wrapping_add(self.y as u16);
+        match self.step {
+            2 => {
+                self.addr_ptr = self.pcread();
+                return 0;
+            }
+            3 => {
+                self.addr_abs = self.read(self.addr_ptr, false).into();
+                return 0;
+            }
+            4 => {
+                let hi: u16 = self.read((self.addr_ptr + 1) & 0x00FF, false).into();
+                return self.index_low_byte((hi << 8) | self.addr_abs, self.y);
+            }
+            _ => {
+                return self.index_high_byte(self.y);
+            }
+        }
+    }
 
-        if (self.addr_abs & 0xFF00) != (hi << 8) {
+    // Indexing only adds to the low byte at first. Reads that stay on the page are
+    // done, everything else spends a cycle reading that address while the high byte
+    // is fixed up
+    fn index_low_byte(&mut self, base: u16, index: u8) -> u8 {
+        self.addr_abs = (base & 0xFF00) | (((base & 0x00FF) + (index as u16)) & 0x00FF);
+        let crossed = (self.addr_abs & 0x00FF) < (index as u16);
+        if !crossed && self.lookup[usize::from(self.opcode)].access == Access::READ {
             return 1;
         } else {
             return 0;
         }
     }
 
+    fn index_high_byte(&mut self, index: u8) -> u8 {
+        self.read(self.addr_abs, false);
+        if (self.addr_abs & 0x00FF) < (index as u16) {
+            self.addr_abs = self.addr_abs.wrapping_add(0x0100);
+        }
+        return 1;
+    }
+
     //Opcodes
 
     /// Instruction: Add with carry in
     pub fn ADC(&mut self) -> u8 {
         self.fetch();
-        let temp: u16 =
-            (self.a as u16) + (self.fetched as u16) + (self.get_flag(FLAGS6502::C) as u16);
-        self.set_flag(FLAGS6502::C, temp > 255);
-        self.set_flag(FLAGS6502::Z, (temp & 0x00FF) == 0);
-        self.set_flag(
-            FLAGS6502::V,
-            (!(self.a as u16 ^ self.fetched as u16) & (self.a as u16 ^ temp)) & 0x0080 > 0,
-        );
-        self.set_flag(FLAGS6502::N, temp & 0x80 > 0);
-        self.a = (temp & 0x00FF) as u8;
+        self.add_with_carry(self.fetched);
         return 1;
     }
 
@@ -240,20 +349,17 @@ impl Cpu {
 
     /// Instruction: Branch if carry is clear
     pub fn BCC(&mut self) -> u8 {
-        self.branchrel(self.get_flag(FLAGS6502::C) == 0);
-        return 0;
+        return self.branchrel(self.get_flag(FLAGS6502::C) == 0);
     }
 
     /// Instruction: Branch if carry bit is set
     pub fn BCS(&mut self) -> u8 {
-        self.branchrel(self.get_flag(FLAGS6502::C) == 1);
-        return 0;
+        return self.branchrel(self.get_flag(FLAGS6502::C) == 1);
     }
 
     /// Instruction: Branch if equal
     pub fn BEQ(&mut self) -> u8 {
-        self.branchrel(self.get_flag(FLAGS6502::Z) == 1);
-        return 0;
+        return self.branchrel(self.get_flag(FLAGS6502::Z) == 1);
     }
 
     pub fn BIT(&mut self) -> u8 {
@@ -267,49 +373,76 @@ impl Cpu {
 
     /// Instruction: Branch if minus
     pub fn BMI(&mut self) -> u8 {
-        self.branchrel(self.get_flag(FLAGS6502::N) == 1);
-        return 0;
+        return self.branchrel(self.get_flag(FLAGS6502::N) == 1);
     }
 
     /// Instruction: Branch if not equal
     pub fn BNE(&mut self) -> u8 {
-        self.branchrel(self.get_flag(FLAGS6502::Z) == 0);
-        return 0;
+        return self.branchrel(self.get_flag(FLAGS6502::Z) == 0);
     }
 
     /// Instruction: Branch if positive
     pub fn BPL(&mut self) -> u8 {
-        self.branchrel(self.get_flag(FLAGS6502::N) == 0);
-        return 0;
+        return self.branchrel(self.get_flag(FLAGS6502::N) == 0);
     }
 
-    /// Instruction: Force Interrupt
+    /// Instruction: Force Interrupt, IRQ and NMI run the same sequence
     pub fn BRK(&mut self) -> u8 {
-        self.pc = self.pc.wrapping_add(1);
-
-        self.set_flag(FLAGS6502::I, true);
-        self.push_to_stack(((self.pc >> 8) & 0x00FF) as u8);
-        self.push_to_stack((self.pc & 0x00FF) as u8);
-
-        self.set_flag(FLAGS6502::B, true);
-        self.push_to_stack(self.status);
-        self.set_flag(FLAGS6502::B, false);
-
-        self.pc = self.read(0xFFFE as u16, false) as u16
-            | ((self.read(0xFFFF as u16, false) as u16) << 8) as u16;
+        match self.step {
+            2 => {
+                // BRK skips a padding byte, hardware interrupts leave pc alone
+                self.read(self.pc, false);
+                if self.interrupt == Interrupt::NONE {
+                    self.pc = self.pc.wrapping_add(1);
+                }
+            }
+            3 => {
+                self.push_to_stack(((self.pc >> 8) & 0x00FF) as u8);
+            }
+            4 => {
+                self.push_to_stack((self.pc & 0x00FF) as u8);
+            }
+            5 => {
+                // B only shows up in the copy BRK pushes
+                let mut status = self.status | (FLAGS6502::U as u8);
+                if self.interrupt == Interrupt::NONE {
+                    status = status | (FLAGS6502::B as u8);
+                }
+                self.push_to_stack(status);
+                self.set_flag(FLAGS6502::I, true);
+            }
+            6 => {
+                // An NMI arriving before the vector is fetched hijacks BRK and IRQ,
+                // they finish with the NMI vector and the NMI is not run again
+                if self.nmi_pending {
+                    self.nmi_pending = false;
+                    self.interrupt = Interrupt::NMI;
+                }
+                if self.interrupt == Interrupt::NMI {
+                    self.addr_abs = 0xFFFA;
+                } else {
+                    self.addr_abs = 0xFFFE;
+                }
+                self.pc = self.read(self.addr_abs, false) as u16;
+            }
+            _ => {
+                let hi = self.read(self.addr_abs + 1, false) as u16;
+                self.pc = (hi << 8) | self.pc;
+                self.interrupt = Interrupt::NONE;
+                return 1;
+            }
+        }
         return 0;
     }
 
     /// Instruction: Branch if overflowed
     pub fn BVC(&mut self) -> u8 {
-        self.branchrel(self.get_flag(FLAGS6502::V) == 0);
-        return 0;
+        return self.branchrel(self.get_flag(FLAGS6502::V) == 0);
     }
 
     /// Instruction: Branch if  not overflowed
     pub fn BVS(&mut self) -> u8 {
-        self.branchrel(self.get_flag(FLAGS6502::V) == 1);
-        return 0;
+        return self.branchrel(self.get_flag(FLAGS6502::V) == 1);
     }
 
     /// Instruction: Clear carry flag
@@ -440,17 +573,40 @@ impl Cpu {
 
     /// Instruction: Jump to address
     pub fn JMP(&mut self) -> u8 {
+        let addrfunc = self.lookup[usize::from(self.opcode)].addrmode;
+        if addrfunc(self) == 0 {
+            return 0;
+        }
         self.pc = self.addr_abs;
-        return 0;
+        if addrfunc as usize == Self::IND as usize {
+            self.bus.cdl_log_prg(self.pc, CDL_CODE | CDL_INDIRECT_CODE);
+        }
+        return 1;
     }
 
     /// Instruction: Jump to subroutine
     pub fn JSR(&mut self) -> u8 {
-        self.pc = self.pc - 1;
-
-        self.push_to_stack(((self.pc >> 8) & 0x00FF) as u8);
-        self.push_to_stack((self.pc & 0x00FF) as u8);
-        self.pc = self.addr_abs;
+        match self.step {
+            2 => {
+                self.addr_abs = self.pcread();
+            }
+            3 => {
+                self.read(0x0100 + self.stkp as u16, false);
+            }
+            4 => {
+                // pc points at the high byte of the target, RTS adds the missing 1
+                self.push_to_stack(((self.pc >> 8) & 0x00FF) as u8);
+            }
+            5 => {
+                self.push_to_stack((self.pc & 0x00FF) as u8);
+            }
+            _ => {
+                let hi = self.pcread();
+                self.addr_abs = (hi << 8) | self.addr_abs;
+                self.pc = self.addr_abs;
+                return 1;
+            }
+        }
         return 0;
     }
 
@@ -498,6 +654,8 @@ impl Cpu {
 
     /// Instruction: No operation
     pub fn NOP(&mut self) -> u8 {
+        // The unofficial variants still read their operand
+        self.fetch();
         match self.opcode {
             0x1C | 0x3C | 0x5C | 0x7C | 0xDC | 0xFC => {
                 return 1;
@@ -514,36 +672,52 @@ impl Cpu {
         self.a = self.a | self.fetched;
         self.set_flag(FLAGS6502::Z, self.a == 0x00);
         self.set_flag(FLAGS6502::N, (self.a & 0x80) > 0);
-        return 0;
+        return 1;
     }
 
     /// Instruction: Push A to stack
     pub fn PHA(&mut self) -> u8 {
+        if self.step == 2 {
+            self.read(self.pc, false);
+            return 0;
+        }
         self.push_to_stack(self.a);
-        return 0;
+        return 1;
     }
 
     /// Instruction: Push status to stack
     pub fn PHP(&mut self) -> u8 {
+        if self.step == 2 {
+            self.read(self.pc, false);
+            return 0;
+        }
         self.push_to_stack(self.status | (FLAGS6502::B as u8) | (FLAGS6502::U as u8));
         self.set_flag(FLAGS6502::B, false);
         self.set_flag(FLAGS6502::U, false);
-        return 0;
+        return 1;
     }
 
     /// Instruction: Pop from stack to accumulator
     pub fn PLA(&mut self) -> u8 {
+        if self.pull_delay() {
+            return 0;
+        }
         self.a = self.pop_from_stack();
         self.set_flag(FLAGS6502::Z, self.a == 0);
         self.set_flag(FLAGS6502::N, (self.a & 0x80) > 0);
-        return 0;
+        return 1;
     }
 
     /// Instruction: Pop from stack to status
     pub fn PLP(&mut self) -> u8 {
+        if self.pull_delay() {
+            return 0;
+        }
+        // B only exists on the stack copy
         self.status = self.pop_from_stack();
+        self.set_flag(FLAGS6502::B, false);
         self.set_flag(FLAGS6502::U, true);
-        return 0;
+        return 1;
     }
 
     /// Instruction: Rotate Left
@@ -579,41 +753,56 @@ impl Cpu {
 
     /// Instruction: Return from interrupt
     pub fn RTI(&mut self) -> u8 {
-        self.status = self.pop_from_stack();
-
-        self.set_flag(FLAGS6502::B, false);
-        self.set_flag(FLAGS6502::U, false);
+        if self.pull_delay() {
+            return 0;
+        }
+        match self.step {
+            4 => {
+                self.status = self.pop_from_stack();
 
-        let lo: u16 = self.pop_from_stack() as u16;
-        let hi: u16 = (self.pop_from_stack() as u16) << 8;
-        self.pc = hi | lo;
+                self.set_flag(FLAGS6502::B, false);
+                self.set_flag(FLAGS6502::U, false);
+            }
+            5 => {
+                self.pc = self.pop_from_stack() as u16;
+            }
+            _ => {
+                let hi: u16 = (self.pop_from_stack() as u16) << 8;
+                self.pc = hi | self.pc;
+                return 1;
+            }
+        }
         return 0;
     }
 
     /// Instruction: Return from subroutine
     pub fn RTS(&mut self) -> u8 {
-        let lo: u16 = self.pop_from_stack() as u16;
-        let hi: u16 = (self.pop_from_stack() as u16) << 8;
-        self.pc = hi | lo;
-        self.pc = self.pc.wrapping_add(1);
+        if self.pull_delay() {
+            return 0;
+        }
+        match self.step {
+            4 => {
+                self.pc = self.pop_from_stack() as u16;
+            }
+            5 => {
+                let hi: u16 = (self.pop_from_stack() as u16) << 8;
+                self.pc = hi | self.pc;
+            }
+            _ => {
+                self.read(self.pc, false);
+                self.pc = self.pc.wrapping_add(1);
+                return 1;
+            }
+        }
         return 0;
     }
 
     /// Instruction: Subtract with carry
     pub fn SBC(&mut self) -> u8 {
         self.fetch();
-        let value: u16 = (self.fetched as u16) ^ 0x00FF;
-
-        let temp = (self.a as u16) + (value as u16) + (self.get_flag(FLAGS6502::C) as u16);
-        self.set_flag(FLAGS6502::C, temp & 0xFF00 > 0);
-        self.set_flag(FLAGS6502::Z, (temp & 0x00FF) == 0);
-        self.set_flag(
-            FLAGS6502::V,
-            (temp ^ (self.a as u16)) & (temp ^ value) & 0x0080 > 0,
-        );
-        self.set_flag(FLAGS6502::N, temp & 0x0080 > 0);
-        self.a = (temp & 0x00FF) as u8;
-        return 0;
+        // Subtraction is addition of the inverted operand
+        self.add_with_carry(self.fetched ^ 0xFF);
+        return 1;
     }
 
     /// Instruction: Set carry flag
@@ -700,40 +889,314 @@ impl Cpu {
 
     /// Instruction: Invalid operation
     pub fn XXX(&mut self) -> u8 {
+        // Still reads its operand so the cycle has its bus access
+        self.fetch();
+        return 0;
+    }
+
+    //Unofficial opcodes
+
+    /// Instruction: AND immediate, then copy N to carry
+    pub fn ANC(&mut self) -> u8 {
+        self.fetch();
+        self.a = self.a & self.fetched;
+        self.set_flag(FLAGS6502::Z, self.a == 0x00);
+        self.set_flag(FLAGS6502::N, (self.a & 0x80) > 0);
+        self.set_flag(FLAGS6502::C, (self.a & 0x80) > 0);
         return 0;
     }
 
-    //Interrupts
+    /// Instruction: AND immediate, then logical shift right A
+    pub fn ALR(&mut self) -> u8 {
+        self.fetch();
+        let temp = self.a & self.fetched;
+        self.set_flag(FLAGS6502::C, (temp & 0x01) > 0);
+        self.a = temp >> 1;
+        self.set_flag(FLAGS6502::Z, self.a == 0x00);
+        self.set_flag(FLAGS6502::N, (self.a & 0x80) > 0);
+        return 0;
+    }
+
+    /// Instruction: AND immediate, then rotate right A with C and V taken from bits 6 and 5
+    pub fn ARR(&mut self) -> u8 {
+        self.fetch();
+        let cval = self.get_flag(FLAGS6502::C);
+        self.a = ((self.a & self.fetched) >> 1) | (cval << 7);
+        self.set_flag(FLAGS6502::Z, self.a == 0x00);
+        self.set_flag(FLAGS6502::N, (self.a & 0x80) > 0);
+        self.set_flag(FLAGS6502::C, (self.a & 0x40) > 0);
+        self.set_flag(FLAGS6502::V, ((self.a >> 6) ^ (self.a >> 5)) & 0x01 > 0);
+        return 0;
+    }
+
+    /// Instruction: X = (A AND X) - immediate, without borrow
+    pub fn AXS(&mut self) -> u8 {
+        self.fetch();
+        let temp = self.a & self.x;
+        self.set_flag(FLAGS6502::C, temp >= self.fetched);
+        self.x = temp.wrapping_sub(self.fetched);
+        self.set_flag(FLAGS6502::Z, self.x == 0x00);
+        self.set_flag(FLAGS6502::N, (self.x & 0x80) > 0);
+        return 0;
+    }
+
+    /// Instruction: Decrement memory, then compare with A
+    pub fn DCP(&mut self) -> u8 {
+        self.fetch();
+        let value = self.fetched.wrapping_sub(1);
+        self.write(usize::from(self.addr_abs), value);
+        let temp = self.a.wrapping_sub(value);
+        self.set_flag(FLAGS6502::C, self.a >= value);
+        self.set_flag(FLAGS6502::Z, temp == 0x00);
+        self.set_flag(FLAGS6502::N, (temp & 0x80) > 0);
+        return 0;
+    }
+
+    /// Instruction: Increment memory, then subtract from A with carry
+    pub fn ISB(&mut self) -> u8 {
+        self.fetch();
+        let value = self.fetched.wrapping_add(1);
+        self.write(usize::from(self.addr_abs), value);
+        self.add_with_carry(value ^ 0xFF);
+        return 0;
+    }
+
+    /// Instruction: Load A and X from memory
+    pub fn LAX(&mut self) -> u8 {
+        self.fetch();
+        self.a = self.fetched;
+        self.x = self.fetched;
+        self.set_flag(FLAGS6502::Z, self.a == 0x00);
+        self.set_flag(FLAGS6502::N, (self.a & 0x80) > 0);
+        return 1;
+    }
+
+    /// Instruction: Rotate memory left, then AND with A
+    pub fn RLA(&mut self) -> u8 {
+        self.fetch();
+        let value = (self.fetched << 1) | self.get_flag(FLAGS6502::C);
+        self.set_flag(FLAGS6502::C, (self.fetched & 0x80) > 0);
+        self.write(usize::from(self.addr_abs), value);
+        self.a = self.a & value;
+        self.set_flag(FLAGS6502::Z, self.a == 0x00);
+        self.set_flag(FLAGS6502::N, (self.a & 0x80) > 0);
+        return 0;
+    }
+
+    /// Instruction: Rotate memory right, then add to A with carry
+    pub fn RRA(&mut self) -> u8 {
+        self.fetch();
+        let value = (self.fetched >> 1) | (self.get_flag(FLAGS6502::C) << 7);
+        self.set_flag(FLAGS6502::C, (self.fetched & 0x01) > 0);
+        self.write(usize::from(self.addr_abs), value);
+        self.add_with_carry(value);
+        return 0;
+    }
+
+    /// Instruction: Store A AND X to memory
+    pub fn SAX(&mut self) -> u8 {
+        self.write(usize::from(self.addr_abs), self.a & self.x);
+        return 0;
+    }
+
+    /// Instruction: Shift memory left, then OR with A
+    pub fn SLO(&mut self) -> u8 {
+        self.fetch();
+        let value = self.fetched << 1;
+        self.set_flag(FLAGS6502::C, (self.fetched & 0x80) > 0);
+        self.write(usize::from(self.addr_abs), value);
+        self.a = self.a | value;
+        self.set_flag(FLAGS6502::Z, self.a == 0x00);
+        self.set_flag(FLAGS6502::N, (self.a & 0x80) > 0);
+        return 0;
+    }
+
+    /// Instruction: Shift memory right, then XOR with A
+    pub fn SRE(&mut self) -> u8 {
+        self.fetch();
+        let value = self.fetched >> 1;
+        self.set_flag(FLAGS6502::C, (self.fetched & 0x01) > 0);
+        self.write(usize::from(self.addr_abs), value);
+        self.a = self.a ^ value;
+        self.set_flag(FLAGS6502::Z, self.a == 0x00);
+        self.set_flag(FLAGS6502::N, (self.a & 0x80) > 0);
+        return 0;
+    }
+
+    // Shared by ADC, SBC and the unofficial opcodes built on them
+    fn add_with_carry(&mut self, value: u8) {
+        let temp: u16 = (self.a as u16) + (value as u16) + (self.get_flag(FLAGS6502::C) as u16);
+        self.set_flag(FLAGS6502::C, temp > 255);
+        self.set_flag(FLAGS6502::Z, (temp & 0x00FF) == 0);
+        self.set_flag(
+            FLAGS6502::V,
+            (!(self.a as u16 ^ value as u16) & (self.a as u16 ^ temp)) & 0x0080 > 0,
+        );
+        self.set_flag(FLAGS6502::N, temp & 0x80 > 0);
+        self.a = (temp & 0x00FF) as u8;
+    }
+
+    /// Run one cpu cycle, every cycle does exactly one bus access
     pub fn clock(&mut self) {
-        if self.cycles == 0 {
-            self.opcode = self.read(self.pc, false).into();
+        // DMA can only halt the cpu on a read cycle, writes go through first
+        if self.bus.dma_pending() && !self.next_cycle_writes() {
+            let addr = self.next_read_addr();
+            self.bus.dma_clock(addr);
+            self.clock_count = self.clock_count + 1;
+            return;
+        }
 
-            self.set_flag(FLAGS6502::U, true);
+        if self.step == 0 {
+            if self.bus.debugger.on_instruction(self.pc) {
+                return;
+            }
+            self.start_instruction();
+            self.poll_interrupts();
+        } else {
+            self.step = self.step + 1;
+            if self.execute_cycle() {
+                // The table has the shortest timing, page crossings and taken branches add to it
+                let instr = &self.lookup[usize::from(self.opcode)];
+                debug_assert!(
+                    instr.name == "???" || self.step >= instr.cycles,
+                    "{} finished after {} cycles",
+                    instr.name,
+                    self.step
+                );
+                self.step = 0;
+                self.set_flag(FLAGS6502::U, true);
+            } else {
+                self.poll_interrupts();
+            }
+        }
+        self.clock_count = self.clock_count + 1;
+    }
+
+    // Whether the cycle clock() runs next is a write
+    fn next_cycle_writes(&self) -> bool {
+        if self.step == 0 {
+            return false;
+        }
+        let next = self.step + 1;
+        let instr = &self.lookup[usize::from(self.opcode)];
+        match instr.access {
+            Access::WRITE => return self.addr_step != 0,
+            Access::RMW => return self.addr_step != 0 && next - self.addr_step >= 2,
+            Access::CONTROL => match self.opcode {
+                // BRK, IRQ and NMI push pc and status, JSR pushes pc
+                0x00 => return next >= 3 && next <= 5,
+                0x20 => return next == 4 || next == 5,
+                0x08 | 0x48 => return next == 3,
+                _ => return false,
+            },
+            Access::READ => return false,
+        }
+    }
+
+    // Address the cpu reads next, repeated on the cycle DMA halts it. Close
+    // enough for the operand and data reads the registers care about
+    fn next_read_addr(&self) -> u16 {
+        if self.step != 0 && self.addr_step != 0 {
+            return self.addr_abs;
+        }
+        return self.pc;
+    }
+
+    // Sample the interrupt lines at the end of a cycle that is not an instruction's last.
+    // CLI, SEI and PLP change I on their last cycle, so the old value still decides
+    // whether an IRQ follows them
+    fn poll_interrupts(&mut self) {
+        // The interrupt sequence itself does not poll, the handler's first instruction runs
+        if self.opcode == 0x00 {
+            return;
+        }
+        // A taken branch that stays on its page skips polling on its second cycle
+        if self.opcode & 0x1F == 0x10 && self.step == 2 {
+            return;
+        }
+        self.nmi_poll = self.nmi_pending;
+        self.irq_poll = self.get_flag(FLAGS6502::I) == 0 && self.bus.irq_line();
+    }
+
+    // First cycle, fetch the opcode or start servicing an interrupt
+    fn start_instruction(&mut self) {
+        if self.nmi_poll {
+            self.nmi_pending = false;
+            self.interrupt = Interrupt::NMI;
+        } else if self.irq_poll {
+            self.interrupt = Interrupt::IRQ;
+        }
+        self.nmi_poll = false;
+        self.irq_poll = false;
+
+        if self.interrupt != Interrupt::NONE {
+            // The opcode is read but BRK's sequence runs in its place
+            self.read(self.pc, false);
+            self.opcode = 0x00;
+        } else {
+            if let Some(mut tracer) = self.tracer.take() {
+                tracer.trace(self);
+                self.tracer = Some(tracer);
+            }
+            self.opcode = self.read(self.pc, false);
+            self.bus.cdl_log_prg(self.pc, CDL_CODE);
             self.pc = self.pc.wrapping_add(1);
+        }
 
-            let instr = &self.lookup[usize::from(self.opcode)];
+        self.set_flag(FLAGS6502::U, true);
+        self.step = 1;
+        self.addr_step = 0;
+    }
 
-            self.cycles = instr.cycles;
+    // Cycles after the opcode fetch, true once the instruction has finished
+    fn execute_cycle(&mut self) -> bool {
+        let instr = &self.lookup[usize::from(self.opcode)];
+        let addrfunc = instr.addrmode;
+        let operfunc = instr.operate;
+        let access = instr.access;
 
-            let addrfunc = instr.addrmode;
-            let operfunc = instr.operate;
-            let additional_cycle1 = addrfunc(self);
-            let additional_cycle2 = operfunc(self);
+        if access == Access::CONTROL {
+            return operfunc(self) == 1;
+        }
 
-            self.cycles = self.cycles + (additional_cycle1 & additional_cycle2);
+        if self.addr_step == 0 {
+            if addrfunc(self) == 0 {
+                return false;
+            }
+            // Implied and immediate instructions operate on their second cycle
+            if addrfunc as usize == Self::IMP as usize || addrfunc as usize == Self::IMM as usize {
+                operfunc(self);
+                return true;
+            }
+            self.addr_step = self.step;
+            return false;
+        }
 
-            self.set_flag(FLAGS6502::U, true);
+        match (access, self.step - self.addr_step) {
+            (Access::RMW, 1) => {
+                self.read_operand();
+                return false;
+            }
+            (Access::RMW, 2) => {
+                // The unmodified value is written back while the result is worked out
+                self.write(usize::from(self.addr_abs), self.fetched);
+                return false;
+            }
+            _ => {
+                operfunc(self);
+                return true;
+            }
         }
-        self.cycles = self.cycles - 1;
     }
 
     pub fn _is_complete(&mut self) -> bool {
-        return self.cycles == 0;
+        return self.step == 0;
     }
 
     /// reset cpu to a known state
     pub fn reset(&mut self) {
         println!("Reset start");
+        self.bus.reset();
         self.addr_abs = 0xFFFC;
 
         let loc1 = self.addr_abs + 0;
@@ -748,59 +1211,204 @@ impl Cpu {
         self.x = 0;
         self.y = 0;
         self.stkp = 0xFD;
-        self.status = 0x00 | (FLAGS6502::U as u8);
+        self.status = 0x00 | (FLAGS6502::U as u8) | (FLAGS6502::I as u8);
 
         self.addr_abs = 0;
         self.addr_rel = 0;
         self.fetched = 0;
 
-        self.cycles = 8;
+        self.step = 0;
+        self.addr_step = 0;
+        self.interrupt = Interrupt::NONE;
+        self.nmi_pending = false;
+        self.nmi_poll = false;
+        self.irq_poll = false;
+
+        // Reset takes 7 cycles, nestest logs start counting from there
+        self.clock_count = 7;
 
-        self.bus.reset();
         println!("Reset end,{}",self.pc);
     }
 
-    /// interrupt only if I=0
-    fn _irq(&mut self) {
-        if self.get_flag(FLAGS6502::I) == 0 {
-            self.push_to_stack(((self.pc >> 8) & 0x00FF) as u8);
-            self.push_to_stack((self.pc & 0x00FF) as u8);
+    /// Non maskable interrupt, serviced once the current instruction finishes
+    pub fn nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Disassemble start..=end into "$C000: JMP $C5F5 {ABS}" lines keyed by address
+    pub fn disassemble(&mut self, start: u16, end: u16) -> BTreeMap<u16, String> {
+        let mut map_lines = BTreeMap::new();
+        let mut addr = start as u32;
 
-            self.set_flag(FLAGS6502::B, false);
-            self.set_flag(FLAGS6502::U, true);
-            self.set_flag(FLAGS6502::I, true);
-            self.push_to_stack(self.status);
+        while addr <= end as u32 {
+            let line_addr = addr as u16;
+            let instr = self.disassemble_instruction(line_addr, false);
+            addr = addr + instr.bytes.len() as u32;
 
-            self.addr_abs = 0xFFFE;
-            let lo = self.read(self.addr_abs + 0, false);
-            let hi = self.read(self.addr_abs + 1, false);
-            self.pc = ((hi as u16) << 8) | (lo as u16);
-            self.cycles = 7;
+            let text = if instr.operand.is_empty() {
+                instr.name
+            } else {
+                format!("{} {}", instr.name, instr.operand)
+            };
+            map_lines.insert(line_addr, format!("${:04X}: {} {{{}}}", line_addr, text, instr.mode));
         }
-    }
 
-    /// Non maskable interrupt
-    pub fn nmi(&mut self) {
-        self.push_to_stack(((self.pc >> 8) & 0x00FF) as u8);
-        self.push_to_stack((self.pc & 0x00FF) as u8);
+        return map_lines;
+    }
 
-        self.set_flag(FLAGS6502::B, false);
-        self.set_flag(FLAGS6502::U, true);
-        self.set_flag(FLAGS6502::I, true);
-        self.push_to_stack(self.status);
+    /// Decode the instruction at pc in nestest syntax. With resolve the operand also
+    /// shows the effective address and value for the current registers, as the tracer logs them
+    pub fn disassemble_instruction(&mut self, pc: u16, resolve: bool) -> Disassembly {
+        let opcode = self.read(pc, true);
+        let instr = &self.lookup[opcode as usize];
+        let name = instr.name.clone();
+        let mut addrmode = instr.addrmode as usize;
+        if opcode == 0x00 {
+            // BRK skips a padding byte but is listed as implied
+            addrmode = Self::IMP as usize;
+        }
 
-        self.addr_abs = 0xFFFA;
-        let lo = self.read(self.addr_abs + 0, false);
-        let hi = self.read(self.addr_abs + 1, false);
+        let (size, mode) = if addrmode == Self::IMP as usize {
+            (1, "IMP")
+        } else if addrmode == Self::IMM as usize {
+            (2, "IMM")
+        } else if addrmode == Self::ZP0 as usize {
+            (2, "ZP0")
+        } else if addrmode == Self::ZPX as usize {
+            (2, "ZPX")
+        } else if addrmode == Self::ZPY as usize {
+            (2, "ZPY")
+        } else if addrmode == Self::REL as usize {
+            (2, "REL")
+        } else if addrmode == Self::ABS as usize {
+            (3, "ABS")
+        } else if addrmode == Self::ABX as usize {
+            (3, "ABX")
+        } else if addrmode == Self::ABY as usize {
+            (3, "ABY")
+        } else if addrmode == Self::IND as usize {
+            (3, "IND")
+        } else if addrmode == Self::IZX as usize {
+            (2, "IZX")
+        } else {
+            (2, "IZY")
+        };
+        let b1 = self.read(pc.wrapping_add(1), true);
+        let b2 = self.read(pc.wrapping_add(2), true);
+        let abs = ((b2 as u16) << 8) | b1 as u16;
+        let mut bytes = vec![opcode, b1, b2];
+        bytes.truncate(size);
+
+        let mnemonic = name.trim_start_matches('*');
+        let operand = match mode {
+            "IMP" => match mnemonic {
+                "ASL" | "LSR" | "ROL" | "ROR" => String::from("A"),
+                _ => String::new(),
+            },
+            "IMM" => format!("#${:02X}", b1),
+            "ZP0" if resolve => format!("${:02X} = {:02X}", b1, self.read(b1 as u16, true)),
+            "ZP0" => format!("${:02X}", b1),
+            "ZPX" | "ZPY" => {
+                let (reg, index) = if mode == "ZPX" { ("X", self.x) } else { ("Y", self.y) };
+                if resolve {
+                    let addr = b1.wrapping_add(index);
+                    format!(
+                        "${:02X},{} @ {:02X} = {:02X}",
+                        b1,
+                        reg,
+                        addr,
+                        self.read(addr as u16, true)
+                    )
+                } else {
+                    format!("${:02X},{}", b1, reg)
+                }
+            }
+            "REL" => {
+                let target = pc.wrapping_add(2).wrapping_add(b1 as i8 as u16);
+                format!("${:04X}", target)
+            }
+            "ABS" => {
+                if !resolve || mnemonic == "JMP" || mnemonic == "JSR" {
+                    format!("${:04X}", abs)
+                } else {
+                    format!("${:04X} = {:02X}", abs, self.read(abs, true))
+                }
+            }
+            "ABX" | "ABY" => {
+                let (reg, index) = if mode == "ABX" { ("X", self.x) } else { ("Y", self.y) };
+                if resolve {
+                    let addr = abs.wrapping_add(index as u16);
+                    format!(
+                        "${:04X},{} @ {:04X} = {:02X}",
+                        abs,
+                        reg,
+                        addr,
+                        self.read(addr, true)
+                    )
+                } else {
+                    format!("${:04X},{}", abs, reg)
+                }
+            }
+            "IND" if resolve => {
+                // Page wrap bug, the high byte is fetched from the start of the page
+                let hi_addr = (abs & 0xFF00) | (abs.wrapping_add(1) & 0x00FF);
+                let target = ((self.read(hi_addr, true) as u16) << 8) | self.read(abs, true) as u16;
+                format!("(${:04X}) = {:04X}", abs, target)
+            }
+            "IND" => format!("(${:04X})", abs),
+            "IZX" if resolve => {
+                let ptr = b1.wrapping_add(self.x);
+                let addr = ((self.read(ptr.wrapping_add(1) as u16, true) as u16) << 8)
+                    | self.read(ptr as u16, true) as u16;
+                format!(
+                    "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                    b1,
+                    ptr,
+                    addr,
+                    self.read(addr, true)
+                )
+            }
+            "IZX" => format!("(${:02X},X)", b1),
+            _ if resolve => {
+                let base = ((self.read(b1.wrapping_add(1) as u16, true) as u16) << 8)
+                    | self.read(b1 as u16, true) as u16;
+                let addr = base.wrapping_add(self.y as u16);
+                format!(
+                    "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                    b1,
+                    base,
+                    addr,
+                    self.read(addr, true)
+                )
+            }
+            _ => format!("(${:02X}),Y", b1),
+        };
 
-        self.pc = ((hi as u16) << 8) | (lo as u16);
-        self.cycles = 8;
+        return Disassembly {
+            bytes: bytes,
+            name: name,
+            operand: operand,
+            mode: mode,
+        };
     }
 
+    // Read-modify-write instructions read their operand two cycles before they operate
     fn fetch(&mut self) {
-        let opsize = self.opcode as usize;
-        if self.lookup[opsize].addrmode as usize != Self::IMP as usize {
-            self.fetched = self.read(self.addr_abs, false);
+        let instr = &self.lookup[self.opcode as usize];
+        if instr.addrmode as usize != Self::IMP as usize && instr.access != Access::RMW {
+            self.read_operand();
+        }
+    }
+
+    fn read_operand(&mut self) {
+        let addrmode = self.lookup[self.opcode as usize].addrmode as usize;
+        self.fetched = self.read(self.addr_abs, false);
+        if addrmode == Self::IMM as usize {
+            self.bus.cdl_log_prg(self.addr_abs, CDL_CODE);
+        } else if addrmode == Self::IZX as usize || addrmode == Self::IZY as usize {
+            self.bus.cdl_log_prg(self.addr_abs, CDL_DATA | CDL_INDIRECT_DATA);
+        } else {
+            self.bus.cdl_log_prg(self.addr_abs, CDL_DATA);
         }
     }
 
@@ -818,283 +1426,328 @@ impl Cpu {
             addr_abs: 0,
             addr_rel: 0,
             opcode: 0,
-            cycles: 0,
+            step: 0,
+            addr_step: 0,
+            addr_ptr: 0,
+            interrupt: Interrupt::NONE,
+            nmi_pending: false,
+            nmi_poll: false,
+            irq_poll: false,
+            clock_count: 0,
+            tracer: None,
             lookup: vec![
                 I::new_i("BRK", Self::BRK, Self::IMM, 7),
                 I::new_i("ORA", Self::ORA, Self::IZX, 6),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
-                I::new_i("???", Self::NOP, Self::IMP, 3),
+                I::new_i("*SLO", Self::SLO, Self::IZX, 8),
+                I::new_i("*NOP", Self::NOP, Self::ZP0, 3),
                 I::new_i("ORA", Self::ORA, Self::ZP0, 3),
                 I::new_i("ASL", Self::ASL, Self::ZP0, 5),
-                I::new_i("???", Self::XXX, Self::IMP, 5),
+                I::new_i("*SLO", Self::SLO, Self::ZP0, 5),
                 I::new_i("PHP", Self::PHP, Self::IMP, 3),
                 I::new_i("ORA", Self::ORA, Self::IMM, 2),
                 I::new_i("ASL", Self::ASL, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*ANC", Self::ANC, Self::IMM, 2),
+                I::new_i("*NOP", Self::NOP, Self::ABS, 4),
                 I::new_i("ORA", Self::ORA, Self::ABS, 4),
                 I::new_i("ASL", Self::ASL, Self::ABS, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*SLO", Self::SLO, Self::ABS, 6),
                 I::new_i("BPL", Self::BPL, Self::REL, 2),
                 I::new_i("ORA", Self::ORA, Self::IZY, 5),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*SLO", Self::SLO, Self::IZY, 8),
+                I::new_i("*NOP", Self::NOP, Self::ZPX, 4),
                 I::new_i("ORA", Self::ORA, Self::ZPX, 4),
                 I::new_i("ASL", Self::ASL, Self::ZPX, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*SLO", Self::SLO, Self::ZPX, 6),
                 I::new_i("CLC", Self::CLC, Self::IMP, 2),
                 I::new_i("ORA", Self::ORA, Self::ABY, 4),
-                I::new_i("???", Self::NOP, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*NOP", Self::NOP, Self::IMP, 2),
+                I::new_i("*SLO", Self::SLO, Self::ABY, 7),
+                I::new_i("*NOP", Self::NOP, Self::ABX, 4),
                 I::new_i("ORA", Self::ORA, Self::ABX, 4),
                 I::new_i("ASL", Self::ASL, Self::ABX, 7),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
+                I::new_i("*SLO", Self::SLO, Self::ABX, 7),
                 I::new_i("JSR", Self::JSR, Self::ABS, 6),
                 I::new_i("AND", Self::AND, Self::IZX, 6),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
+                I::new_i("*RLA", Self::RLA, Self::IZX, 8),
                 I::new_i("BIT", Self::BIT, Self::ZP0, 3),
                 I::new_i("AND", Self::AND, Self::ZP0, 3),
                 I::new_i("ROL", Self::ROL, Self::ZP0, 5),
-                I::new_i("???", Self::XXX, Self::IMP, 5),
+                I::new_i("*RLA", Self::RLA, Self::ZP0, 5),
                 I::new_i("PLP", Self::PLP, Self::IMP, 4),
                 I::new_i("AND", Self::AND, Self::IMM, 2),
                 I::new_i("ROL", Self::ROL, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 2),
+                I::new_i("*ANC", Self::ANC, Self::IMM, 2),
                 I::new_i("BIT", Self::BIT, Self::ABS, 4),
                 I::new_i("AND", Self::AND, Self::ABS, 4),
                 I::new_i("ROL", Self::ROL, Self::ABS, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*RLA", Self::RLA, Self::ABS, 6),
                 I::new_i("BMI", Self::BMI, Self::REL, 2),
                 I::new_i("AND", Self::AND, Self::IZY, 5),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*RLA", Self::RLA, Self::IZY, 8),
+                I::new_i("*NOP", Self::NOP, Self::ZPX, 4),
                 I::new_i("AND", Self::AND, Self::ZPX, 4),
                 I::new_i("ROL", Self::ROL, Self::ZPX, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*RLA", Self::RLA, Self::ZPX, 6),
                 I::new_i("SEC", Self::SEC, Self::IMP, 2),
                 I::new_i("AND", Self::AND, Self::ABY, 4),
-                I::new_i("???", Self::NOP, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*NOP", Self::NOP, Self::IMP, 2),
+                I::new_i("*RLA", Self::RLA, Self::ABY, 7),
+                I::new_i("*NOP", Self::NOP, Self::ABX, 4),
                 I::new_i("AND", Self::AND, Self::ABX, 4),
                 I::new_i("ROL", Self::ROL, Self::ABX, 7),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
+                I::new_i("*RLA", Self::RLA, Self::ABX, 7),
                 I::new_i("RTI", Self::RTI, Self::IMP, 6),
                 I::new_i("EOR", Self::EOR, Self::IZX, 6),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
-                I::new_i("???", Self::NOP, Self::IMP, 3),
+                I::new_i("*SRE", Self::SRE, Self::IZX, 8),
+                I::new_i("*NOP", Self::NOP, Self::ZP0, 3),
                 I::new_i("EOR", Self::EOR, Self::ZP0, 3),
                 I::new_i("LSR", Self::LSR, Self::ZP0, 5),
-                I::new_i("???", Self::XXX, Self::IMP, 5),
+                I::new_i("*SRE", Self::SRE, Self::ZP0, 5),
                 I::new_i("PHA", Self::PHA, Self::IMP, 3),
                 I::new_i("EOR", Self::EOR, Self::IMM, 2),
                 I::new_i("LSR", Self::LSR, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 2),
+                I::new_i("*ALR", Self::ALR, Self::IMM, 2),
                 I::new_i("JMP", Self::JMP, Self::ABS, 3),
                 I::new_i("EOR", Self::EOR, Self::ABS, 4),
                 I::new_i("LSR", Self::LSR, Self::ABS, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*SRE", Self::SRE, Self::ABS, 6),
                 I::new_i("BVC", Self::BVC, Self::REL, 2),
                 I::new_i("EOR", Self::EOR, Self::IZY, 5),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*SRE", Self::SRE, Self::IZY, 8),
+                I::new_i("*NOP", Self::NOP, Self::ZPX, 4),
                 I::new_i("EOR", Self::EOR, Self::ZPX, 4),
                 I::new_i("LSR", Self::LSR, Self::ZPX, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*SRE", Self::SRE, Self::ZPX, 6),
                 I::new_i("CLI", Self::CLI, Self::IMP, 2),
                 I::new_i("EOR", Self::EOR, Self::ABY, 4),
-                I::new_i("???", Self::NOP, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*NOP", Self::NOP, Self::IMP, 2),
+                I::new_i("*SRE", Self::SRE, Self::ABY, 7),
+                I::new_i("*NOP", Self::NOP, Self::ABX, 4),
                 I::new_i("EOR", Self::EOR, Self::ABX, 4),
                 I::new_i("LSR", Self::LSR, Self::ABX, 7),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
+                I::new_i("*SRE", Self::SRE, Self::ABX, 7),
                 I::new_i("RTS", Self::RTS, Self::IMP, 6),
                 I::new_i("ADC", Self::ADC, Self::IZX, 6),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
-                I::new_i("???", Self::NOP, Self::IMP, 3),
+                I::new_i("*RRA", Self::RRA, Self::IZX, 8),
+                I::new_i("*NOP", Self::NOP, Self::ZP0, 3),
                 I::new_i("ADC", Self::ADC, Self::ZP0, 3),
                 I::new_i("ROR", Self::ROR, Self::ZP0, 5),
-                I::new_i("???", Self::XXX, Self::IMP, 5),
+                I::new_i("*RRA", Self::RRA, Self::ZP0, 5),
                 I::new_i("PLA", Self::PLA, Self::IMP, 4),
                 I::new_i("ADC", Self::ADC, Self::IMM, 2),
                 I::new_i("ROR", Self::ROR, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 2),
+                I::new_i("*ARR", Self::ARR, Self::IMM, 2),
                 I::new_i("JMP", Self::JMP, Self::IND, 5),
                 I::new_i("ADC", Self::ADC, Self::ABS, 4),
                 I::new_i("ROR", Self::ROR, Self::ABS, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*RRA", Self::RRA, Self::ABS, 6),
                 I::new_i("BVS", Self::BVS, Self::REL, 2),
                 I::new_i("ADC", Self::ADC, Self::IZY, 5),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*RRA", Self::RRA, Self::IZY, 8),
+                I::new_i("*NOP", Self::NOP, Self::ZPX, 4),
                 I::new_i("ADC", Self::ADC, Self::ZPX, 4),
                 I::new_i("ROR", Self::ROR, Self::ZPX, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*RRA", Self::RRA, Self::ZPX, 6),
                 I::new_i("SEI", Self::SEI, Self::IMP, 2),
                 I::new_i("ADC", Self::ADC, Self::ABY, 4),
-                I::new_i("???", Self::NOP, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*NOP", Self::NOP, Self::IMP, 2),
+                I::new_i("*RRA", Self::RRA, Self::ABY, 7),
+                I::new_i("*NOP", Self::NOP, Self::ABX, 4),
                 I::new_i("ADC", Self::ADC, Self::ABX, 4),
                 I::new_i("ROR", Self::ROR, Self::ABX, 7),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
-                I::new_i("???", Self::NOP, Self::IMP, 2),
+                I::new_i("*RRA", Self::RRA, Self::ABX, 7),
+                I::new_i("*NOP", Self::NOP, Self::IMM, 2),
                 I::new_i("STA", Self::STA, Self::IZX, 6),
-                I::new_i("???", Self::NOP, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*NOP", Self::NOP, Self::IMM, 2),
+                I::new_i("*SAX", Self::SAX, Self::IZX, 6),
                 I::new_i("STY", Self::STY, Self::ZP0, 3),
                 I::new_i("STA", Self::STA, Self::ZP0, 3),
                 I::new_i("STX", Self::STX, Self::ZP0, 3),
-                I::new_i("???", Self::XXX, Self::IMP, 3),
+                I::new_i("*SAX", Self::SAX, Self::ZP0, 3),
                 I::new_i("DEY", Self::DEY, Self::IMP, 2),
-                I::new_i("???", Self::NOP, Self::IMP, 2),
+                I::new_i("*NOP", Self::NOP, Self::IMM, 2),
                 I::new_i("TXA", Self::TXA, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 2),
+                I::new_i("???", Self::XXX, Self::IMM, 2),
                 I::new_i("STY", Self::STY, Self::ABS, 4),
                 I::new_i("STA", Self::STA, Self::ABS, 4),
                 I::new_i("STX", Self::STX, Self::ABS, 4),
-                I::new_i("???", Self::XXX, Self::IMP, 4),
+                I::new_i("*SAX", Self::SAX, Self::ABS, 4),
                 I::new_i("BCC", Self::BCC, Self::REL, 2),
                 I::new_i("STA", Self::STA, Self::IZY, 6),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("???", Self::XXX, Self::IZY, 6),
                 I::new_i("STY", Self::STY, Self::ZPX, 4),
                 I::new_i("STA", Self::STA, Self::ZPX, 4),
                 I::new_i("STX", Self::STX, Self::ZPY, 4),
-                I::new_i("???", Self::XXX, Self::IMP, 4),
+                I::new_i("*SAX", Self::SAX, Self::ZPY, 4),
                 I::new_i("TYA", Self::TYA, Self::IMP, 2),
                 I::new_i("STA", Self::STA, Self::ABY, 5),
                 I::new_i("TXS", Self::TXS, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 5),
-                I::new_i("???", Self::NOP, Self::IMP, 5),
+                I::new_i("???", Self::XXX, Self::ABY, 5),
+                I::new_i("???", Self::XXX, Self::ABX, 5),
                 I::new_i("STA", Self::STA, Self::ABX, 5),
-                I::new_i("???", Self::XXX, Self::IMP, 5),
-                I::new_i("???", Self::XXX, Self::IMP, 5),
+                I::new_i("???", Self::XXX, Self::ABY, 5),
+                I::new_i("???", Self::XXX, Self::ABY, 5),
                 I::new_i("LDY", Self::LDY, Self::IMM, 2),
                 I::new_i("LDA", Self::LDA, Self::IZX, 6),
                 I::new_i("LDX", Self::LDX, Self::IMM, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*LAX", Self::LAX, Self::IZX, 6),
                 I::new_i("LDY", Self::LDY, Self::ZP0, 3),
                 I::new_i("LDA", Self::LDA, Self::ZP0, 3),
                 I::new_i("LDX", Self::LDX, Self::ZP0, 3),
-                I::new_i("???", Self::XXX, Self::IMP, 3),
+                I::new_i("*LAX", Self::LAX, Self::ZP0, 3),
                 I::new_i("TAY", Self::TAY, Self::IMP, 2),
                 I::new_i("LDA", Self::LDA, Self::IMM, 2),
                 I::new_i("TAX", Self::TAX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 2),
+                I::new_i("*LAX", Self::LAX, Self::IMM, 2),
                 I::new_i("LDY", Self::LDY, Self::ABS, 4),
                 I::new_i("LDA", Self::LDA, Self::ABS, 4),
                 I::new_i("LDX", Self::LDX, Self::ABS, 4),
-                I::new_i("???", Self::XXX, Self::IMP, 4),
+                I::new_i("*LAX", Self::LAX, Self::ABS, 4),
                 I::new_i("BCS", Self::BCS, Self::REL, 2),
                 I::new_i("LDA", Self::LDA, Self::IZY, 5),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 5),
+                I::new_i("*LAX", Self::LAX, Self::IZY, 5),
                 I::new_i("LDY", Self::LDY, Self::ZPX, 4),
                 I::new_i("LDA", Self::LDA, Self::ZPX, 4),
                 I::new_i("LDX", Self::LDX, Self::ZPY, 4),
-                I::new_i("???", Self::XXX, Self::IMP, 4),
+                I::new_i("*LAX", Self::LAX, Self::ZPY, 4),
                 I::new_i("CLV", Self::CLV, Self::IMP, 2),
                 I::new_i("LDA", Self::LDA, Self::ABY, 4),
                 I::new_i("TSX", Self::TSX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 4),
+                I::new_i("???", Self::XXX, Self::ABY, 4),
                 I::new_i("LDY", Self::LDY, Self::ABX, 4),
                 I::new_i("LDA", Self::LDA, Self::ABX, 4),
                 I::new_i("LDX", Self::LDX, Self::ABY, 4),
-                I::new_i("???", Self::XXX, Self::IMP, 4),
+                I::new_i("*LAX", Self::LAX, Self::ABY, 4),
                 I::new_i("CPY", Self::CPY, Self::IMM, 2),
                 I::new_i("CMP", Self::CMP, Self::IZX, 6),
-                I::new_i("???", Self::NOP, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
+                I::new_i("*NOP", Self::NOP, Self::IMM, 2),
+                I::new_i("*DCP", Self::DCP, Self::IZX, 8),
                 I::new_i("CPY", Self::CPY, Self::ZP0, 3),
                 I::new_i("CMP", Self::CMP, Self::ZP0, 3),
                 I::new_i("DEC", Self::DEC, Self::ZP0, 5),
-                I::new_i("???", Self::XXX, Self::IMP, 5),
+                I::new_i("*DCP", Self::DCP, Self::ZP0, 5),
                 I::new_i("INY", Self::INY, Self::IMP, 2),
                 I::new_i("CMP", Self::CMP, Self::IMM, 2),
                 I::new_i("DEX", Self::DEX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 2),
+                I::new_i("*AXS", Self::AXS, Self::IMM, 2),
                 I::new_i("CPY", Self::CPY, Self::ABS, 4),
                 I::new_i("CMP", Self::CMP, Self::ABS, 4),
                 I::new_i("DEC", Self::DEC, Self::ABS, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*DCP", Self::DCP, Self::ABS, 6),
                 I::new_i("BNE", Self::BNE, Self::REL, 2),
                 I::new_i("CMP", Self::CMP, Self::IZY, 5),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*DCP", Self::DCP, Self::IZY, 8),
+                I::new_i("*NOP", Self::NOP, Self::ZPX, 4),
                 I::new_i("CMP", Self::CMP, Self::ZPX, 4),
                 I::new_i("DEC", Self::DEC, Self::ZPX, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*DCP", Self::DCP, Self::ZPX, 6),
                 I::new_i("CLD", Self::CLD, Self::IMP, 2),
                 I::new_i("CMP", Self::CMP, Self::ABY, 4),
-                I::new_i("NOP", Self::NOP, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*NOP", Self::NOP, Self::IMP, 2),
+                I::new_i("*DCP", Self::DCP, Self::ABY, 7),
+                I::new_i("*NOP", Self::NOP, Self::ABX, 4),
                 I::new_i("CMP", Self::CMP, Self::ABX, 4),
                 I::new_i("DEC", Self::DEC, Self::ABX, 7),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
+                I::new_i("*DCP", Self::DCP, Self::ABX, 7),
                 I::new_i("CPX", Self::CPX, Self::IMM, 2),
                 I::new_i("SBC", Self::SBC, Self::IZX, 6),
-                I::new_i("???", Self::NOP, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
+                I::new_i("*NOP", Self::NOP, Self::IMM, 2),
+                I::new_i("*ISB", Self::ISB, Self::IZX, 8),
                 I::new_i("CPX", Self::CPX, Self::ZP0, 3),
                 I::new_i("SBC", Self::SBC, Self::ZP0, 3),
                 I::new_i("INC", Self::INC, Self::ZP0, 5),
-                I::new_i("???", Self::XXX, Self::IMP, 5),
+                I::new_i("*ISB", Self::ISB, Self::ZP0, 5),
                 I::new_i("INX", Self::INX, Self::IMP, 2),
                 I::new_i("SBC", Self::SBC, Self::IMM, 2),
                 I::new_i("NOP", Self::NOP, Self::IMP, 2),
-                I::new_i("???", Self::SBC, Self::IMP, 2),
+                I::new_i("*SBC", Self::SBC, Self::IMM, 2),
                 I::new_i("CPX", Self::CPX, Self::ABS, 4),
                 I::new_i("SBC", Self::SBC, Self::ABS, 4),
                 I::new_i("INC", Self::INC, Self::ABS, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*ISB", Self::ISB, Self::ABS, 6),
                 I::new_i("BEQ", Self::BEQ, Self::REL, 2),
                 I::new_i("SBC", Self::SBC, Self::IZY, 5),
                 I::new_i("???", Self::XXX, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 8),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*ISB", Self::ISB, Self::IZY, 8),
+                I::new_i("*NOP", Self::NOP, Self::ZPX, 4),
                 I::new_i("SBC", Self::SBC, Self::ZPX, 4),
                 I::new_i("INC", Self::INC, Self::ZPX, 6),
-                I::new_i("???", Self::XXX, Self::IMP, 6),
+                I::new_i("*ISB", Self::ISB, Self::ZPX, 6),
                 I::new_i("SED", Self::SED, Self::IMP, 2),
                 I::new_i("SBC", Self::SBC, Self::ABY, 4),
-                I::new_i("NOP", Self::NOP, Self::IMP, 2),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
-                I::new_i("???", Self::NOP, Self::IMP, 4),
+                I::new_i("*NOP", Self::NOP, Self::IMP, 2),
+                I::new_i("*ISB", Self::ISB, Self::ABY, 7),
+                I::new_i("*NOP", Self::NOP, Self::ABX, 4),
                 I::new_i("SBC", Self::SBC, Self::ABX, 4),
                 I::new_i("INC", Self::INC, Self::ABX, 7),
-                I::new_i("???", Self::XXX, Self::IMP, 7),
+                I::new_i("*ISB", Self::ISB, Self::ABX, 7),
             ]
         };
     }
 
+    // Operand bytes following the opcode
     fn pcread(&mut self) -> u16 {
         let result = self.read(self.pc, false).into();
-        self.pc = self.pc + 1;
+        self.bus.cdl_log_prg(self.pc, CDL_CODE);
+        self.pc = self.pc.wrapping_add(1);
         return result;
     }
 
-    fn branchrel(&mut self, flag: bool) {
-        if flag {
-            self.cycles = self.cycles + 1;
-            self.addr_abs = self.addr_rel.wrapping_add(self.pc);
-            if (self.addr_abs & 0xFF00) != (self.pc & 0xFF00) {
-                self.cycles = self.cycles + 1;
+    // Pulls read the next byte, then the stack before the pointer moves
+    fn pull_delay(&mut self) -> bool {
+        match self.step {
+            2 => {
+                self.read(self.pc, false);
+                return true;
             }
+            3 => {
+                self.read(0x0100 + self.stkp as u16, false);
+                return true;
+            }
+            _ => {
+                return false;
+            }
+        }
+    }
 
-            self.pc = self.addr_abs;
+    // Taken branches add a cycle, and one more to fix pc's high byte when
+    // the target is on another page
+    fn branchrel(&mut self, flag: bool) -> u8 {
+        match self.step {
+            2 => {
+                self.REL();
+                if flag {
+                    return 0;
+                } else {
+                    return 1;
+                }
+            }
+            3 => {
+                self.read(self.pc, false);
+                self.addr_abs = self.addr_rel.wrapping_add(self.pc);
+                if (self.addr_abs & 0xFF00) == (self.pc & 0xFF00) {
+                    self.pc = self.addr_abs;
+                    return 1;
+                }
+                self.pc = (self.pc & 0xFF00) | (self.addr_abs & 0x00FF);
+                return 0;
+            }
+            _ => {
+                self.read(self.pc, false);
+                self.pc = self.addr_abs;
+                return 1;
+            }
         }
     }
 
@@ -0,0 +1,230 @@
+use crate::cpu::{Cpu, FLAGS6502};
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WatchKind {
+    READ,
+    WRITE,
+    EXECUTE,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub kind: WatchKind,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum StepMode {
+    NONE,
+    INSTRUCTION,
+    SCANLINE,
+    FRAME,
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum BreakReason {
+    PAUSE,
+    STEP,
+    BREAKPOINT(u16),
+    WATCHPOINT(u16, WatchKind),
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BreakReason::PAUSE => write!(f, "Paused"),
+            BreakReason::STEP => write!(f, "Step"),
+            BreakReason::BREAKPOINT(addr) => write!(f, "Breakpoint at ${:04X}", addr),
+            BreakReason::WATCHPOINT(addr, kind) => {
+                write!(f, "Watchpoint {:?} at ${:04X}", kind, addr)
+            }
+        }
+    }
+}
+
+// Lives on the bus so memory accesses can be checked, the cpu asks it before
+// every instruction and the nes before every ppu clock
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    step: StepMode,
+    paused: bool,
+    // Instruction we stopped in front of, None when stopped in the middle of one
+    paused_pc: Option<u16>,
+    // Lets that instruction run when resuming
+    resume_pc: Option<u16>,
+    reason: Option<BreakReason>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        return Debugger {
+            breakpoints: BTreeSet::new(),
+            watchpoints: vec![],
+            step: StepMode::NONE,
+            paused: false,
+            paused_pc: None,
+            resume_pc: None,
+            reason: None,
+        };
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn breakpoints(&self) -> Vec<u16> {
+        return self.breakpoints.iter().cloned().collect();
+    }
+
+    /// Break on any access of the given kind in start..=end
+    pub fn add_watchpoint(&mut self, start: u16, end: u16, kind: WatchKind) {
+        let watchpoint = Watchpoint {
+            start: start.min(end),
+            end: start.max(end),
+            kind,
+        };
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+
+    pub fn remove_watchpoint(&mut self, start: u16, end: u16, kind: WatchKind) {
+        self.watchpoints
+            .retain(|w| !(w.start == start && w.end == end && w.kind == kind));
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        return &self.watchpoints;
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        return self.paused;
+    }
+
+    pub fn break_reason(&self) -> Option<BreakReason> {
+        return self.reason;
+    }
+
+    pub fn pause(&mut self) {
+        self.break_on(BreakReason::PAUSE);
+    }
+
+    pub fn resume(&mut self) {
+        self.run(StepMode::NONE);
+    }
+
+    pub fn step_instruction(&mut self) {
+        self.run(StepMode::INSTRUCTION);
+    }
+
+    pub fn step_scanline(&mut self) {
+        self.run(StepMode::SCANLINE);
+    }
+
+    pub fn step_frame(&mut self) {
+        self.run(StepMode::FRAME);
+    }
+
+    fn run(&mut self, step: StepMode) {
+        self.step = step;
+        self.resume_pc = if self.paused { self.paused_pc } else { None };
+        self.paused = false;
+        self.reason = None;
+    }
+
+    fn break_on(&mut self, reason: BreakReason) {
+        self.paused = true;
+        self.paused_pc = None;
+        self.step = StepMode::NONE;
+        self.reason = Some(reason);
+    }
+
+    fn watching(&self, addr: u16, kind: WatchKind) -> bool {
+        return self
+            .watchpoints
+            .iter()
+            .any(|w| w.kind == kind && addr >= w.start && addr <= w.end);
+    }
+
+    /// Called before the cpu fetches an instruction, true means do not execute it
+    pub fn on_instruction(&mut self, pc: u16) -> bool {
+        if self.paused {
+            return true;
+        }
+        if self.resume_pc.take() == Some(pc) {
+            return false;
+        }
+        if self.step == StepMode::INSTRUCTION {
+            self.break_on(BreakReason::STEP);
+        } else if self.breakpoints.contains(&pc) {
+            self.break_on(BreakReason::BREAKPOINT(pc));
+        } else if self.watching(pc, WatchKind::EXECUTE) {
+            self.break_on(BreakReason::WATCHPOINT(pc, WatchKind::EXECUTE));
+        }
+        if self.paused {
+            self.paused_pc = Some(pc);
+        }
+        return self.paused;
+    }
+
+    pub fn on_read(&mut self, addr: u16) {
+        if self.watching(addr, WatchKind::READ) {
+            self.break_on(BreakReason::WATCHPOINT(addr, WatchKind::READ));
+        }
+    }
+
+    pub fn on_write(&mut self, addr: u16) {
+        if self.watching(addr, WatchKind::WRITE) {
+            self.break_on(BreakReason::WATCHPOINT(addr, WatchKind::WRITE));
+        }
+    }
+
+    /// Called after every ppu clock with its new position
+    pub fn on_ppu_clock(&mut self, scanline: i16, cycle: i16) {
+        match self.step {
+            StepMode::SCANLINE if cycle == 0 => self.break_on(BreakReason::STEP),
+            StepMode::FRAME if scanline == -1 && cycle == 0 => self.break_on(BreakReason::STEP),
+            _ => {}
+        }
+    }
+}
+
+/// One line summary of the cpu registers, set flags are upper case
+pub fn register_dump(cpu: &Cpu) -> String {
+    let flags = [
+        (FLAGS6502::N, 'N'),
+        (FLAGS6502::V, 'V'),
+        (FLAGS6502::U, 'U'),
+        (FLAGS6502::B, 'B'),
+        (FLAGS6502::D, 'D'),
+        (FLAGS6502::I, 'I'),
+        (FLAGS6502::Z, 'Z'),
+        (FLAGS6502::C, 'C'),
+    ];
+    let mut flag_text = String::new();
+    for (flag, name) in flags {
+        if cpu.get_flag(flag) > 0 {
+            flag_text.push(name);
+        } else {
+            flag_text.push(name.to_ascii_lowercase());
+        }
+    }
+
+    return format!(
+        "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} [{}] CYC:{}",
+        cpu.pc, cpu.a, cpu.x, cpu.y, cpu.stkp, cpu.status, flag_text, cpu.clock_count
+    );
+}
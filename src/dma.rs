@@ -0,0 +1,106 @@
+// Sprite (OAM) and DMC sample DMA. Either one halts the cpu on its next read
+// cycle, after that the unit reads on "get" cycles and writes on "put" cycles,
+// which alternate with the APU clock. The bus runs it while the cpu is halted
+pub struct Dma {
+    halted: bool,
+
+    oam_active: bool,
+    oam_page: u8,
+    oam_count: u16,
+    oam_data: u8,
+    oam_loaded: bool,
+
+    dmc_active: bool,
+    dmc_addr: u16,
+    // Halt and dummy cycles the DMC waits before it may take a get cycle
+    dmc_delay: u8,
+}
+
+// What the unit wants the bus for on one cycle
+#[allow(non_camel_case_types)]
+pub enum DmaCycle {
+    // First halted cycle, the cpu's own read goes through
+    HALT,
+    // Dummy or alignment cycle
+    IDLE,
+    OAM_READ(u16),
+    OAM_WRITE(u8, u8),
+    DMC_READ(u16),
+}
+
+impl Dma {
+    pub fn new() -> Self {
+        return Dma {
+            halted: false,
+            oam_active: false,
+            oam_page: 0,
+            oam_count: 0,
+            oam_data: 0,
+            oam_loaded: false,
+            dmc_active: false,
+            dmc_addr: 0,
+            dmc_delay: 0,
+        };
+    }
+
+    pub fn reset(&mut self) {
+        *self = Dma::new();
+    }
+
+    /// Copy $xx00-$xxFF to OAM, 513 or 514 cycles depending on alignment
+    pub fn start_oam(&mut self, page: u8) {
+        self.oam_active = true;
+        self.oam_page = page;
+        self.oam_count = 0;
+        self.oam_loaded = false;
+    }
+
+    /// Fetch one DMC sample byte, 3 or 4 cycles on its own, usually 2 during OAM DMA
+    pub fn start_dmc(&mut self, addr: u16) {
+        if !self.dmc_active {
+            self.dmc_active = true;
+            self.dmc_addr = addr;
+            self.dmc_delay = 2;
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        return self.oam_active || self.dmc_active;
+    }
+
+    /// Decide one halted cpu cycle, get cycles are the ones that start an APU cycle
+    pub fn clock(&mut self, get: bool) -> DmaCycle {
+        let mut cycle = DmaCycle::IDLE;
+        if !self.halted {
+            self.halted = true;
+            cycle = DmaCycle::HALT;
+        } else if get && self.dmc_active && self.dmc_delay == 0 {
+            // The DMC wins a get cycle over OAM, which then needs to realign
+            self.dmc_active = false;
+            cycle = DmaCycle::DMC_READ(self.dmc_addr);
+        } else if self.oam_active && get && !self.oam_loaded {
+            cycle = DmaCycle::OAM_READ(((self.oam_page as u16) << 8) | self.oam_count);
+        } else if self.oam_active && !get && self.oam_loaded {
+            cycle = DmaCycle::OAM_WRITE(self.oam_count as u8, self.oam_data);
+            self.oam_loaded = false;
+            self.oam_count = self.oam_count + 1;
+            if self.oam_count == 256 {
+                self.oam_active = false;
+            }
+        }
+
+        // OAM keeps transferring through the DMC's halt and dummy cycles
+        if self.dmc_active && self.dmc_delay > 0 {
+            self.dmc_delay = self.dmc_delay - 1;
+        }
+        if !self.is_active() {
+            self.halted = false;
+        }
+        return cycle;
+    }
+
+    pub fn oam_loaded(&mut self, data: u8) {
+        self.oam_data = data;
+        self.oam_loaded = true;
+    }
+}
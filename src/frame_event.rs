@@ -0,0 +1,39 @@
+use crate::gui::Message;
+use crate::nes::NES_PTR;
+use iced_futures::futures;
+
+// Delivers every finished frame of the Nes in NES_PTR. The stream ends when that
+// Nes is replaced, bump generation to subscribe to the new one
+pub struct FrameEventRecipe {
+    pub generation: u64,
+}
+
+impl<H, I> iced_futures::subscription::Recipe<H, I> for FrameEventRecipe
+where
+    H: std::hash::Hasher,
+{
+    type Output = Message;
+
+    fn hash(&self, state: &mut H) {
+        use std::hash::Hash;
+        std::any::TypeId::of::<Self>().hash(state);
+        self.generation.hash(state);
+    }
+
+    fn stream(
+        self: Box<Self>,
+        _input: futures::stream::BoxStream<'static, I>,
+    ) -> futures::stream::BoxStream<'static, Self::Output> {
+        let frames = NES_PTR.lock().unwrap().subscribe_frames();
+        Box::pin(futures::stream::unfold(frames, |frames| async move {
+            // Waiting on the channel blocks, keep it off the executor
+            let (frame, frames) = tokio::task::spawn_blocking(move || (frames.recv(), frames))
+                .await
+                .ok()?;
+            match frame {
+                Ok(frame) => Some((Message::Frame(frame), frames)),
+                Err(_) => None,
+            }
+        }))
+    }
+}
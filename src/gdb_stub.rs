@@ -0,0 +1,498 @@
+use crate::debugger::{BreakReason, WatchKind};
+use crate::nes::Nes;
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// GDB remote serial protocol over TCP. Registers are sent in the order
+// A, X, Y, P, SP as single bytes followed by the little endian PC.
+const REGISTER_COUNT: usize = 6;
+const MAX_MEMORY_READ: usize = 0x1000;
+
+pub struct GdbStub {
+    nes: Arc<Mutex<Nes>>,
+    stream: TcpStream,
+}
+
+impl GdbStub {
+    /// Listen on localhost and serve one debugger connection at a time
+    pub fn listen(port: u16, nes: Arc<Mutex<Nes>>) -> io::Result<thread::JoinHandle<()>> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("GDB stub listening on 127.0.0.1:{}", port);
+        return Ok(thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let mut stub = GdbStub {
+                            nes: Arc::clone(&nes),
+                            stream,
+                        };
+                        if let Err(err) = stub.run() {
+                            eprintln!("GDB connection closed: {}", err);
+                        }
+                    }
+                    Err(err) => eprintln!("GDB connection failed: {}", err),
+                }
+            }
+        }));
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        // The target is stopped while a debugger is attached and not continuing
+        self.nes.lock().unwrap().cpu.bus.debugger.pause();
+
+        let result = self.serve();
+
+        self.nes.lock().unwrap().cpu.bus.debugger.resume();
+        return result;
+    }
+
+    fn serve(&mut self) -> io::Result<()> {
+        loop {
+            let packet = match self.read_packet()? {
+                Some(packet) => packet,
+                None => return Ok(()),
+            };
+            match self.handle(&packet)? {
+                Some(reply) => self.send_packet(&reply)?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        return read_byte(&mut self.stream);
+    }
+
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        return read_packet(&mut self.stream);
+    }
+
+    fn send_packet(&mut self, body: &str) -> io::Result<()> {
+        return send_packet(&mut self.stream, body);
+    }
+
+    // None closes the connection
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let command = packet.chars().next().unwrap_or(' ');
+        let args = packet.get(1..).unwrap_or("");
+
+        let reply = match command {
+            '?' => self.stop_reply(),
+            '\x03' => {
+                self.nes.lock().unwrap().cpu.bus.debugger.pause();
+                self.stop_reply()
+            }
+            'g' => self.read_registers(),
+            'G' => self.write_registers(args),
+            'p' => self.read_register(args),
+            'P' => self.write_register(args),
+            'm' => self.read_memory(args),
+            'M' => self.write_memory(args),
+            'Z' | 'z' => self.set_breakpoint(command == 'Z', args),
+            'c' | 's' => {
+                {
+                    let mut nes = self.nes.lock().unwrap();
+                    if let Some(addr) = parse_hex(args) {
+                        nes.cpu.pc = addr as u16;
+                    }
+                    if command == 'c' {
+                        nes.cpu.bus.debugger.resume();
+                    } else {
+                        nes.cpu.bus.debugger.step_instruction();
+                    }
+                }
+                self.wait_for_stop()?;
+                self.stop_reply()
+            }
+            'D' => {
+                self.send_packet("OK")?;
+                return Ok(None);
+            }
+            'k' => return Ok(None),
+            'H' => String::from("OK"),
+            'q' => {
+                if packet.starts_with("qSupported") {
+                    format!("PacketSize={:x}", MAX_MEMORY_READ * 2 + 16)
+                } else if packet == "qAttached" {
+                    String::from("1")
+                } else if packet == "qC" {
+                    String::from("QC1")
+                } else {
+                    String::new()
+                }
+            }
+            _ => String::new(),
+        };
+        return Ok(Some(reply));
+    }
+
+    // Poll until the debugger pauses, a ^C from the client pauses it too
+    fn wait_for_stop(&mut self) -> io::Result<()> {
+        self.stream.set_read_timeout(Some(Duration::from_millis(10)))?;
+        let mut result = Ok(());
+        loop {
+            if self.nes.lock().unwrap().cpu.bus.debugger.is_paused() {
+                break;
+            }
+            match self.read_byte() {
+                Ok(Some(0x03)) => self.nes.lock().unwrap().cpu.bus.debugger.pause(),
+                Ok(Some(_)) => {}
+                Ok(None) => {
+                    let err = io::Error::new(io::ErrorKind::ConnectionAborted, "client hung up");
+                    result = Err(err);
+                    break;
+                }
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::TimedOut => {}
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        self.stream.set_read_timeout(None)?;
+        return result;
+    }
+
+    fn stop_reply(&self) -> String {
+        let nes = self.nes.lock().unwrap();
+        match nes.cpu.bus.debugger.break_reason() {
+            Some(BreakReason::WATCHPOINT(addr, WatchKind::WRITE)) => {
+                format!("T05watch:{:x};", addr)
+            }
+            Some(BreakReason::WATCHPOINT(addr, WatchKind::READ)) => {
+                format!("T05rwatch:{:x};", addr)
+            }
+            Some(BreakReason::PAUSE) => String::from("S02"),
+            _ => String::from("S05"),
+        }
+    }
+
+    fn read_registers(&self) -> String {
+        let nes = self.nes.lock().unwrap();
+        let cpu = &nes.cpu;
+        return format!(
+            "{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+            cpu.a,
+            cpu.x,
+            cpu.y,
+            cpu.status,
+            cpu.stkp,
+            cpu.pc & 0x00FF,
+            cpu.pc >> 8
+        );
+    }
+
+    fn write_registers(&self, args: &str) -> String {
+        let bytes = match parse_hex_bytes(args) {
+            Some(bytes) if bytes.len() == REGISTER_COUNT + 1 => bytes,
+            _ => return String::from("E01"),
+        };
+        let mut nes = self.nes.lock().unwrap();
+        let cpu = &mut nes.cpu;
+        cpu.a = bytes[0];
+        cpu.x = bytes[1];
+        cpu.y = bytes[2];
+        cpu.status = bytes[3];
+        cpu.stkp = bytes[4];
+        cpu.pc = ((bytes[6] as u16) << 8) | bytes[5] as u16;
+        return String::from("OK");
+    }
+
+    fn read_register(&self, args: &str) -> String {
+        let nes = self.nes.lock().unwrap();
+        let cpu = &nes.cpu;
+        match parse_hex(args) {
+            Some(0) => format!("{:02x}", cpu.a),
+            Some(1) => format!("{:02x}", cpu.x),
+            Some(2) => format!("{:02x}", cpu.y),
+            Some(3) => format!("{:02x}", cpu.status),
+            Some(4) => format!("{:02x}", cpu.stkp),
+            Some(5) => format!("{:02x}{:02x}", cpu.pc & 0x00FF, cpu.pc >> 8),
+            _ => String::from("E01"),
+        }
+    }
+
+    fn write_register(&self, args: &str) -> String {
+        let (index, value) = match args.split_once('=') {
+            Some((index, value)) => (parse_hex(index), parse_hex_bytes(value)),
+            None => return String::from("E01"),
+        };
+        let value = match value {
+            Some(value) if !value.is_empty() => value,
+            _ => return String::from("E01"),
+        };
+        let mut nes = self.nes.lock().unwrap();
+        let cpu = &mut nes.cpu;
+        match index {
+            Some(0) => cpu.a = value[0],
+            Some(1) => cpu.x = value[0],
+            Some(2) => cpu.y = value[0],
+            Some(3) => cpu.status = value[0],
+            Some(4) => cpu.stkp = value[0],
+            Some(5) if value.len() >= 2 => cpu.pc = ((value[1] as u16) << 8) | value[0] as u16,
+            _ => return String::from("E01"),
+        }
+        return String::from("OK");
+    }
+
+    fn read_memory(&self, args: &str) -> String {
+        let (addr, len) = match parse_addr_len(args) {
+            Some(range) => range,
+            None => return String::from("E01"),
+        };
+        let nes = self.nes.lock().unwrap();
+        let mut reply = String::new();
+        for i in 0..len.min(MAX_MEMORY_READ) {
+            let data = nes.cpu.bus.peek((addr + i) as u16);
+            reply.push_str(&format!("{:02x}", data));
+        }
+        return reply;
+    }
+
+    fn write_memory(&self, args: &str) -> String {
+        let (addr, data) = match parse_write(args) {
+            Some(write) => write,
+            None => return String::from("E01"),
+        };
+        let mut nes = self.nes.lock().unwrap();
+        if !nes.cpu.bus.has_cartridge() {
+            return String::from("E02");
+        }
+        // Poke so the client's own writes don't trip its watchpoints
+        for (i, data) in data.iter().enumerate() {
+            nes.cpu.bus.poke(((addr + i) & 0xFFFF) as u16, *data);
+        }
+        return String::from("OK");
+    }
+
+    // Z0/Z1 breakpoint, Z2 write, Z3 read and Z4 access watchpoints
+    fn set_breakpoint(&self, insert: bool, args: &str) -> String {
+        let (kind, addr, end) = match parse_breakpoint(args) {
+            Some(breakpoint) => breakpoint,
+            None => return String::from("E01"),
+        };
+
+        let mut nes = self.nes.lock().unwrap();
+        let debugger = &mut nes.cpu.bus.debugger;
+        let watch_kinds: &[WatchKind] = match kind {
+            0 | 1 => {
+                if insert {
+                    debugger.add_breakpoint(addr);
+                } else {
+                    debugger.remove_breakpoint(addr);
+                }
+                return String::from("OK");
+            }
+            2 => &[WatchKind::WRITE],
+            3 => &[WatchKind::READ],
+            4 => &[WatchKind::READ, WatchKind::WRITE],
+            _ => return String::new(),
+        };
+        for watch_kind in watch_kinds {
+            if insert {
+                debugger.add_watchpoint(addr, end, *watch_kind);
+            } else {
+                debugger.remove_watchpoint(addr, end, *watch_kind);
+            }
+        }
+        return String::from("OK");
+    }
+}
+
+fn read_byte<R: Read>(stream: &mut R) -> io::Result<Option<u8>> {
+    let mut byte = [0u8; 1];
+    if stream.read(&mut byte)? == 0 {
+        return Ok(None);
+    }
+    return Ok(Some(byte[0]));
+}
+
+// Returns the packet body, "\x03" for an interrupt, None when the client hangs up.
+// Every packet is acknowledged with + or - depending on its checksum
+fn read_packet<S: Read + Write>(stream: &mut S) -> io::Result<Option<String>> {
+    loop {
+        match read_byte(stream)? {
+            None => return Ok(None),
+            Some(0x03) => return Ok(Some(String::from("\x03"))),
+            Some(b'$') => {}
+            Some(_) => continue,
+        }
+
+        let mut body = Vec::new();
+        loop {
+            match read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(b) => body.push(b),
+            }
+        }
+        let mut checksum_digits = [0u8; 2];
+        stream.read_exact(&mut checksum_digits)?;
+
+        let expected = u8::from_str_radix(&String::from_utf8_lossy(&checksum_digits), 16).ok();
+        if expected == Some(checksum(&body)) {
+            stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&body).into_owned()));
+        }
+        stream.write_all(b"-")?;
+    }
+}
+
+fn send_packet<W: Write>(stream: &mut W, body: &str) -> io::Result<()> {
+    let packet = format!("${}#{:02x}", body, checksum(body.as_bytes()));
+    stream.write_all(packet.as_bytes())?;
+    return stream.flush();
+}
+
+// Sum of the body bytes modulo 256
+fn checksum(body: &[u8]) -> u8 {
+    return body.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+}
+
+fn parse_hex(text: &str) -> Option<usize> {
+    return usize::from_str_radix(text, 16).ok();
+}
+
+fn parse_addr_len(text: &str) -> Option<(usize, usize)> {
+    let (addr, len) = text.split_once(',')?;
+    return Some((parse_hex(addr)?, parse_hex(len)?));
+}
+
+// "addr,len:data" of an M packet, the data has to be len bytes long
+fn parse_write(text: &str) -> Option<(usize, Vec<u8>)> {
+    let (range, data) = text.split_once(':')?;
+    let (addr, len) = parse_addr_len(range)?;
+    let data = parse_hex_bytes(data)?;
+    if data.len() != len {
+        return None;
+    }
+    return Some((addr, data));
+}
+
+// "kind,addr[,len]" of a Z or z packet as kind and the inclusive address range
+fn parse_breakpoint(text: &str) -> Option<(usize, u16, u16)> {
+    let mut parts = text.split(',');
+    let kind = parse_hex(parts.next()?)?;
+    let addr = parse_hex(parts.next()?)? as u16;
+    let len = match parts.next() {
+        Some(len) => parse_hex(len)?.max(1),
+        None => 1,
+    };
+    let end = addr.saturating_add((len.min(0x10000) - 1) as u16);
+    return Some((kind, addr, end));
+}
+
+fn parse_hex_bytes(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 2 != 0 {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    for i in (0..text.len()).step_by(2) {
+        bytes.push(u8::from_str_radix(text.get(i..i + 2)?, 16).ok()?);
+    }
+    return Some(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Client bytes in, stub bytes out
+    struct Link {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Link {
+        fn new(input: &[u8]) -> Link {
+            return Link {
+                input: Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            };
+        }
+    }
+
+    impl Read for Link {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            return self.input.read(buf);
+        }
+    }
+
+    impl Write for Link {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            return self.output.write(buf);
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(checksum(b""), 0x00);
+        assert_eq!(checksum(b"OK"), 0x9a);
+        assert_eq!(checksum(b"qSupported"), 0x37);
+        // Wraps at 256
+        assert_eq!(checksum(&[0xFF, 0x02]), 0x01);
+    }
+
+    #[test]
+    fn packet_framing() {
+        // Leading acks are skipped, a good packet is acked with +
+        let mut link = Link::new(b"+$g#67");
+        assert_eq!(read_packet(&mut link).unwrap(), Some(String::from("g")));
+        assert_eq!(link.output, b"+");
+
+        // A bad checksum is nacked and the retransmission accepted
+        let mut link = Link::new(b"$m0,2#00$m0,2#fb");
+        assert_eq!(read_packet(&mut link).unwrap(), Some(String::from("m0,2")));
+        assert_eq!(link.output, b"-+");
+
+        let mut link = Link::new(b"\x03");
+        assert_eq!(read_packet(&mut link).unwrap(), Some(String::from("\x03")));
+
+        // Hanging up mid packet
+        let mut link = Link::new(b"$g");
+        assert_eq!(read_packet(&mut link).unwrap(), None);
+
+        let mut link = Link::new(b"");
+        send_packet(&mut link, "OK").unwrap();
+        assert_eq!(link.output, b"$OK#9a");
+    }
+
+    #[test]
+    fn memory_packets() {
+        assert_eq!(parse_addr_len("c000,10"), Some((0xC000, 0x10)));
+        assert_eq!(parse_addr_len("c000"), None);
+        assert_eq!(parse_addr_len("zz,1"), None);
+
+        assert_eq!(parse_write("10,2:abcd"), Some((0x10, vec![0xAB, 0xCD])));
+        // Length and data have to agree
+        assert_eq!(parse_write("10,3:abcd"), None);
+        assert_eq!(parse_write("10,1:a"), None);
+        assert_eq!(parse_write("10,1"), None);
+    }
+
+    #[test]
+    fn breakpoint_packets() {
+        assert_eq!(parse_breakpoint("0,c000,1"), Some((0, 0xC000, 0xC000)));
+        assert_eq!(parse_breakpoint("2,200,4"), Some((2, 0x0200, 0x0203)));
+        // Missing or zero length watches a single byte
+        assert_eq!(parse_breakpoint("3,10"), Some((3, 0x0010, 0x0010)));
+        assert_eq!(parse_breakpoint("3,10,0"), Some((3, 0x0010, 0x0010)));
+        // Ranges past the top of memory stop there
+        assert_eq!(parse_breakpoint("4,0,10000"), Some((4, 0x0000, 0xFFFF)));
+        assert_eq!(parse_breakpoint("4,ff00,20000"), Some((4, 0xFF00, 0xFFFF)));
+        assert_eq!(parse_breakpoint("2"), None);
+        assert_eq!(parse_breakpoint("2,x"), None);
+        assert_eq!(parse_breakpoint("2,10,x"), None);
+    }
+}
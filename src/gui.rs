@@ -1,18 +1,28 @@
-use crate::nes::Nes;
+use crate::cartridge::Cartridge;
+use crate::config::{Config, REGION_AUTO};
+use crate::debugger::{register_dump, WatchKind};
+use crate::frame_event::FrameEventRecipe;
+use crate::nes::{Frame, Nes};
 use crate::nes::NES_PTR;
 use crate::nes::SPRITE_ARR_SIZE;
+use crate::palette::{Palette, BUILTIN_PALETTES};
+use crate::region::{Region, REGION_NAMES};
 use crate::rtc::client::start_client;
 use crate::rtc::server::start_server;
 use crate::rtc::DATA_CHANNEL_TX;
 use crate::rtc_event::RtcEvent;
 use crate::rtc_event::RtcEventRecipe;
 use crate::screen::Screen;
+use crate::tracer::Tracer;
 use hyper::body::Bytes;
 use iced::time;
 use iced::{
-    button, executor, text_input, Application, Button, Clipboard, Column, Command, Container,
-    Element, HorizontalAlignment, Length, Row, Settings, Subscription, Text, TextInput,
+    button, executor, pick_list, text_input, Application, Button, Clipboard, Column, Command,
+    Container, Element, HorizontalAlignment, Length, PickList, Row, Settings, Subscription, Text,
+    TextInput,
 };
+use std::path::Path;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use iced_aw::{modal, Card, Modal};
@@ -27,10 +37,44 @@ struct DialogState {
     ok_state: button::State,
 }
 
+#[derive(Clone, Debug)]
+pub enum DebugMessage {
+    AddressChanged(String),
+    TogglePause,
+    StepInstruction,
+    StepScanline,
+    StepFrame,
+    Breakpoint,
+    Watch(WatchKind),
+    Clear,
+    ToggleCdl,
+    SaveCdl,
+}
+
+#[derive(Default)]
+struct DebugState {
+    address: String,
+    info: String,
+    ti_address: text_input::State,
+    bt_pause: button::State,
+    bt_step: button::State,
+    bt_step_line: button::State,
+    bt_step_frame: button::State,
+    bt_break: button::State,
+    bt_watch_read: button::State,
+    bt_watch_write: button::State,
+    bt_watch_exec: button::State,
+    bt_clear: button::State,
+    bt_cdl: button::State,
+    bt_save_cdl: button::State,
+    cdl: bool,
+}
+
 #[derive(Default)]
 pub struct State {
     sdp: String,
     rom: String,
+    rom_info: String,
     connection_status: Connection,
     ti_sdp: text_input::State,
     bt_copy: button::State,
@@ -40,16 +84,103 @@ pub struct State {
     bt_stop: button::State,
     bt_browse: button::State,
     modal_state: modal::State<DialogState>,
+    modal_title: String,
+    modal_message: String,
     message_count: u64,
     key_state: u8,
     screen: Screen,
     started: bool,
+    last_save: Option<Instant>,
+    debug: DebugState,
+    config: Config,
+    // Built in palettes followed by any .pal file picked this session
+    palette_options: Vec<String>,
+    pl_palette: pick_list::State<String>,
+    bt_load_palette: button::State,
+    region_options: Vec<String>,
+    pl_region: pick_list::State<String>,
+    bt_scale: button::State,
+    bt_aspect: button::State,
+    // Bumped whenever NES_PTR gets a new Nes so the frame subscription follows it
+    nes_generation: u64,
 }
 
+// How often battery backed ram is flushed to disk while running
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
 pub struct MainMenu {
     state: State,
 }
 
+impl State {
+    fn show_error(&mut self, title: &str, message: String) {
+        self.modal_title = title.to_owned();
+        self.modal_message = message;
+        self.modal_state.show(true);
+    }
+
+    // Hand the configured palette to the running Nes, a broken .pal falls back to the default
+    fn apply_palette(&mut self) {
+        let palette = match Palette::builtin(&self.config.palette) {
+            Some(palette) => palette,
+            None => match Palette::load(Path::new(&self.config.palette)) {
+                Ok(palette) => palette,
+                Err(err) => {
+                    self.show_error("Could not load palette", err.to_string());
+                    self.config.palette = BUILTIN_PALETTES[0].to_owned();
+                    Palette::new()
+                }
+            },
+        };
+        NES_PTR.lock().unwrap().set_palette(palette);
+    }
+
+    // Run the Nes at the configured region, Auto goes by the cartridge header
+    fn apply_region(&mut self) {
+        let mut nes = NES_PTR.lock().unwrap();
+        let region = match Region::from_name(&self.config.region) {
+            Some(region) => region,
+            None => nes.cart_region(),
+        };
+        nes.set_region(region);
+        self.screen.set_region(region);
+    }
+
+    fn show_invalid_address(&mut self) {
+        self.show_error(
+            "Invalid Value",
+            "Enter a valid address in ip:port format".to_owned(),
+        );
+    }
+}
+
+// Accepts "C000", "$C000" or a "8000-80FF" range
+fn parse_address_range(text: &str) -> Option<(u16, u16)> {
+    let parse = |s: &str| u16::from_str_radix(s.trim().trim_start_matches('$'), 16).ok();
+    match text.split_once('-') {
+        Some((start, end)) => Some((parse(start)?, parse(end)?)),
+        None => {
+            let addr = parse(text)?;
+            Some((addr, addr))
+        }
+    }
+}
+
+// Send a frame's palette indices to the connected client
+fn stream_frame(frame: Arc<Frame>) {
+    tokio::spawn(async move {
+        let data_channel = DATA_CHANNEL_TX.lock().await;
+        let data_channel = match data_channel.clone() {
+            Some(dc) => dc,
+            None => return,
+        };
+        let data = Bytes::copy_from_slice(&frame.positions[0..SPRITE_ARR_SIZE]);
+        if let Err(err) = data_channel.write(&data).await {
+            println!("Not Sent, {}", err);
+        }
+    });
+}
+
 #[derive(std::cmp::PartialEq)]
 enum Connection {
     Client,
@@ -74,7 +205,14 @@ pub enum Message {
     Connect,
     RtcEvent(RtcEvent),
     DialogEvent(DialogMessage),
+    Debug(DebugMessage),
+    PaletteSelected(String),
+    LoadPalette,
+    RegionSelected(String),
+    CycleScale,
+    ToggleAspect,
     Tick(Instant),
+    Frame(Arc<Frame>),
     NativeEvent(iced_native::Event),
 }
 
@@ -97,12 +235,18 @@ impl Application for MainMenu {
     type Flags = ();
 
     fn new(_flags: ()) -> (MainMenu, Command<Message>) {
-        (
-            MainMenu {
-                state: State::default(),
-            },
-            Command::none(),
-        )
+        let mut state = State::default();
+        state.config = Config::load();
+        state.palette_options = BUILTIN_PALETTES.iter().map(|name| name.to_string()).collect();
+        if !state.palette_options.contains(&state.config.palette) {
+            state.palette_options.push(state.config.palette.clone());
+        }
+        state.apply_palette();
+        state.region_options = std::iter::once(REGION_AUTO)
+            .chain(REGION_NAMES)
+            .map(|name| name.to_string())
+            .collect();
+        (MainMenu { state: state }, Command::none())
     }
 
     fn title(&self) -> String {
@@ -145,9 +289,114 @@ impl Application for MainMenu {
             .push(Button::new(&mut state.bt_stop, Text::new("Stop")).on_press(Message::StopNes))
             .push(Text::new(&state.rom));
 
-        let canvas = state.screen.view();
+        let info_block = Row::new().push(Text::new(&state.rom_info).size(14));
 
-        let content = Column::new().push(sdp_block).push(input_block).push(canvas);
+        let scale_label = format!("{}x", state.screen.scale());
+        let aspect_label = if state.screen.aspect() { "TV" } else { "1:1" };
+        let display_block = Row::new()
+            .push(Text::new("Palette"))
+            .push(PickList::new(
+                &mut state.pl_palette,
+                &state.palette_options,
+                Some(state.config.palette.clone()),
+                Message::PaletteSelected,
+            ))
+            .push(
+                Button::new(&mut state.bt_load_palette, Text::new("Load .pal"))
+                    .on_press(Message::LoadPalette),
+            )
+            .push(Text::new("Region"))
+            .push(PickList::new(
+                &mut state.pl_region,
+                &state.region_options,
+                Some(state.config.region.clone()),
+                Message::RegionSelected,
+            ))
+            .push(
+                Button::new(&mut state.bt_scale, Text::new(scale_label))
+                    .on_press(Message::CycleScale),
+            )
+            .push(
+                Button::new(&mut state.bt_aspect, Text::new(aspect_label))
+                    .on_press(Message::ToggleAspect),
+            );
+
+        let debug = &mut state.debug;
+        let debug_block = Column::new()
+            .push(
+                Row::new()
+                    .push(
+                        Button::new(&mut debug.bt_pause, Text::new("Pause/Continue"))
+                            .on_press(Message::Debug(DebugMessage::TogglePause)),
+                    )
+                    .push(
+                        Button::new(&mut debug.bt_step, Text::new("Step"))
+                            .on_press(Message::Debug(DebugMessage::StepInstruction)),
+                    )
+                    .push(
+                        Button::new(&mut debug.bt_step_line, Text::new("Scanline"))
+                            .on_press(Message::Debug(DebugMessage::StepScanline)),
+                    )
+                    .push(
+                        Button::new(&mut debug.bt_step_frame, Text::new("Frame"))
+                            .on_press(Message::Debug(DebugMessage::StepFrame)),
+                    )
+                    .push(
+                        Button::new(
+                            &mut debug.bt_cdl,
+                            Text::new(if debug.cdl { "Stop CDL" } else { "Log CDL" }),
+                        )
+                        .on_press(Message::Debug(DebugMessage::ToggleCdl)),
+                    )
+                    .push(
+                        Button::new(&mut debug.bt_save_cdl, Text::new("Save CDL"))
+                            .on_press(Message::Debug(DebugMessage::SaveCdl)),
+                    ),
+            )
+            .push(
+                Row::new()
+                    .push(
+                        TextInput::new(
+                            &mut debug.ti_address,
+                            "C000 or 8000-80FF",
+                            &debug.address,
+                            |s| Message::Debug(DebugMessage::AddressChanged(s)),
+                        )
+                        .width(Length::Units(150))
+                        .padding(5),
+                    )
+                    .push(
+                        Button::new(&mut debug.bt_break, Text::new("Break"))
+                            .on_press(Message::Debug(DebugMessage::Breakpoint)),
+                    )
+                    .push(
+                        Button::new(&mut debug.bt_watch_read, Text::new("R"))
+                            .on_press(Message::Debug(DebugMessage::Watch(WatchKind::READ))),
+                    )
+                    .push(
+                        Button::new(&mut debug.bt_watch_write, Text::new("W"))
+                            .on_press(Message::Debug(DebugMessage::Watch(WatchKind::WRITE))),
+                    )
+                    .push(
+                        Button::new(&mut debug.bt_watch_exec, Text::new("X"))
+                            .on_press(Message::Debug(DebugMessage::Watch(WatchKind::EXECUTE))),
+                    )
+                    .push(
+                        Button::new(&mut debug.bt_clear, Text::new("Clear"))
+                            .on_press(Message::Debug(DebugMessage::Clear)),
+                    ),
+            )
+            .push(Text::new(&debug.info).size(14));
+
+        let screen = state.screen.view();
+
+        let content = Column::new()
+            .push(sdp_block)
+            .push(input_block)
+            .push(info_block)
+            .push(display_block)
+            .push(debug_block)
+            .push(screen);
 
         let main_content = Container::new(content)
             .width(Length::Shrink)
@@ -155,11 +404,10 @@ impl Application for MainMenu {
             .center_x()
             .center_y();
 
-        Modal::new(&mut state.modal_state, main_content, |state| {
-            Card::new(
-                Text::new("Invalid Value"),
-                Text::new("Enter a valid address in ip:port format"),
-            )
+        let modal_title = state.modal_title.clone();
+        let modal_message = state.modal_message.clone();
+        Modal::new(&mut state.modal_state, main_content, move |state| {
+            Card::new(Text::new(&modal_title), Text::new(&modal_message))
             .foot(
                 Row::new().spacing(10).padding(5).width(Length::Fill).push(
                     Button::new(
@@ -190,11 +438,17 @@ impl Application for MainMenu {
             ]),
             Connection::Server => Subscription::batch([
                 Subscription::from_recipe(RtcEventRecipe {}),
-                time::every(Duration::from_millis(10)).map(Message::Tick),
+                Subscription::from_recipe(FrameEventRecipe {
+                    generation: state.nes_generation,
+                }),
+                time::every(Duration::from_millis(100)).map(Message::Tick),
                 iced_native::subscription::events().map(Message::NativeEvent),
             ]),
             _ => Subscription::batch([
-                time::every(Duration::from_millis(10)).map(Message::Tick),
+                Subscription::from_recipe(FrameEventRecipe {
+                    generation: state.nes_generation,
+                }),
+                time::every(Duration::from_millis(100)).map(Message::Tick),
                 iced_native::subscription::events().map(Message::NativeEvent),
             ]),
         }
@@ -213,10 +467,48 @@ impl Application for MainMenu {
                     None => state.rom = "null".to_string(),
                 }
             }
+            Message::PaletteSelected(palette) => {
+                state.config.palette = palette;
+                state.apply_palette();
+                state.config.save();
+            }
+            Message::LoadPalette => {
+                if let Some(file) = tinyfiledialogs::open_file_dialog(
+                    "Open",
+                    "",
+                    Some((&["*.pal"], "NES Palette")),
+                ) {
+                    if !state.palette_options.contains(&file) {
+                        state.palette_options.push(file.clone());
+                    }
+                    state.config.palette = file;
+                    state.apply_palette();
+                    state.config.save();
+                }
+            }
+            Message::RegionSelected(region) => {
+                state.config.region = region;
+                state.apply_region();
+                state.config.save();
+            }
+            Message::Frame(frame) => {
+                state.screen.show_frame(Arc::clone(&frame));
+                if state.connection_status == Connection::Server {
+                    stream_frame(frame);
+                }
+            }
+            Message::CycleScale => {
+                let scale = state.screen.scale() % 4 + 1;
+                state.screen.set_scale(scale);
+            }
+            Message::ToggleAspect => {
+                let aspect = state.screen.aspect();
+                state.screen.set_aspect(!aspect);
+            }
             Message::Connect => {
                 let ip = state.sdp.clone();
                 if ip.is_empty() {
-                    state.modal_state.show(true);
+                    state.show_invalid_address();
                 } else {
                     tokio::spawn(async {
                         if let Err(e) = start_client(ip).await {
@@ -234,7 +526,7 @@ impl Application for MainMenu {
             Message::GenerateSDP => {
                 let ip = state.sdp.clone();
                 if ip.is_empty() {
-                    state.modal_state.show(true);
+                    state.show_invalid_address();
                 } else {
                     tokio::spawn(async {
                         if let Err(e) = start_server(ip).await {
@@ -251,12 +543,42 @@ impl Application for MainMenu {
                 if state.rom.is_empty() && state.connection_status == Connection::Server {
                     return Command::none();
                 } else {
-                    let mut nes = NES_PTR.lock().unwrap();
-                    (*nes) = Nes::new(&state.rom);
-                    drop(nes);
                     if state.connection_status != Connection::Client {
+                        let cart = match Cartridge::load(&state.rom) {
+                            Ok(cart) => cart,
+                            Err(err) => {
+                                state.show_error("Could not load ROM", err.to_string());
+                                return Command::none();
+                            }
+                        };
+                        state.rom_info = cart.rom_info.to_string();
+                        let mut nes = NES_PTR.lock().unwrap();
+                        nes.save_battery_ram();
+                        (*nes) = Nes::new();
+                        state.nes_generation = state.nes_generation + 1;
+                        nes.insert_cartridge(cart);
+                        state.debug.cdl = false;
+                        // Opt in to a nestest style cpu log, "-" writes to stdout
+                        if let Ok(path) = std::env::var("NES_TRACE") {
+                            if path == "-" {
+                                nes.set_tracer(Some(Tracer::to_stdout()));
+                            } else {
+                                match Tracer::to_file(&path) {
+                                    Ok(tracer) => nes.set_tracer(Some(tracer)),
+                                    Err(err) => eprintln!("Could not create {}: {}", path, err),
+                                }
+                            }
+                        }
+                        drop(nes);
                         state.screen.init_nes();
+                        state.apply_region();
+                    } else {
+                        let mut nes = NES_PTR.lock().unwrap();
+                        (*nes) = Nes::new();
+                        state.nes_generation = state.nes_generation + 1;
+                        drop(nes);
                     }
+                    state.apply_palette();
                     if !state.started {
                         state
                             .screen
@@ -268,15 +590,26 @@ impl Application for MainMenu {
             Message::StopNes => {
                 state.screen.stop_nes();
                 state.started = false;
+                let nes = NES_PTR.lock().unwrap();
+                nes.save_battery_ram();
+                if state.debug.cdl {
+                    nes.save_cdl(&Path::new(&state.rom).with_extension("cdl"));
+                }
             }
             Message::RtcEvent(event) => match event {
                 RtcEvent::Message(message) => {
                     if state.connection_status == Connection::Client {
                         let mut nes = NES_PTR.lock().unwrap();
                         // println!("mess {}",message.len());
-                        (*nes).pal_positions = message;
+                        (*nes).set_pal_positions(message);
+                        let frame = Arc::new(Frame {
+                            number: state.message_count,
+                            rgba: nes.frame.clone(),
+                            positions: Vec::new(),
+                        });
+                        drop(nes);
                         state.message_count += 1;
-                        state.screen.request_redraw();
+                        state.screen.show_frame(frame);
                     } else {
                         // println!("received {}", message[0]);
                         let mut nes = NES_PTR.lock().unwrap();
@@ -290,6 +623,66 @@ impl Application for MainMenu {
                 }
                 _ => {}
             },
+            Message::Debug(event) => {
+                let range = parse_address_range(&state.debug.address);
+                match event {
+                    DebugMessage::AddressChanged(value) => {
+                        state.debug.address = value;
+                        return Command::none();
+                    }
+                    DebugMessage::Breakpoint | DebugMessage::Watch(_) if range.is_none() => {
+                        state.show_error(
+                            "Invalid Value",
+                            "Enter a hex address or range".to_owned(),
+                        );
+                        return Command::none();
+                    }
+                    _ => {}
+                }
+
+                let mut nes = NES_PTR.lock().unwrap();
+                // The log lives next to the ROM, as FCEUX does it
+                let cdl_path = Path::new(&state.rom).with_extension("cdl");
+                match event {
+                    DebugMessage::ToggleCdl => {
+                        if state.debug.cdl {
+                            nes.save_cdl(&cdl_path);
+                            nes.stop_cdl();
+                        } else {
+                            nes.start_cdl(&cdl_path);
+                        }
+                        state.debug.cdl = !state.debug.cdl;
+                        return Command::none();
+                    }
+                    DebugMessage::SaveCdl => {
+                        nes.save_cdl(&cdl_path);
+                        return Command::none();
+                    }
+                    _ => {}
+                }
+                let debugger = &mut nes.cpu.bus.debugger;
+                match event {
+                    DebugMessage::TogglePause => {
+                        if debugger.is_paused() {
+                            debugger.resume();
+                        } else {
+                            debugger.pause();
+                        }
+                    }
+                    DebugMessage::StepInstruction => debugger.step_instruction(),
+                    DebugMessage::StepScanline => debugger.step_scanline(),
+                    DebugMessage::StepFrame => debugger.step_frame(),
+                    DebugMessage::Breakpoint => debugger.add_breakpoint(range.unwrap().0),
+                    DebugMessage::Watch(kind) => {
+                        let (start, end) = range.unwrap();
+                        debugger.add_watchpoint(start, end, kind);
+                    }
+                    DebugMessage::Clear => debugger.clear(),
+                    DebugMessage::AddressChanged(_)
+                    | DebugMessage::ToggleCdl
+                    | DebugMessage::SaveCdl => {}
+                }
+            }
             Message::DialogEvent(event) => match event {
                 _ => {
                     state.modal_state.show(false);
@@ -398,60 +791,58 @@ impl Application for MainMenu {
                     _ => {}
                 }
             }
-            Message::Tick(_) => {
-                match state.connection_status {
-                    Connection::Client => {
-                        println!("Frames: {}", state.message_count);
-                        state.message_count = 0;
-                        // state.screen.request_redraw();
+            Message::Tick(now) => {
+                if state.started && state.connection_status != Connection::Client {
+                    let mut nes = NES_PTR.lock().unwrap();
+                    let mut info = register_dump(&nes.cpu);
+                    if nes.cpu.bus.debugger.is_paused() {
+                        // Upcoming instructions, at most 3 bytes each
+                        let pc = nes.cpu.pc;
+                        let lines = nes.cpu.disassemble(pc, pc.saturating_add(11));
+                        for line in lines.values().take(4) {
+                            info = format!("{}\n{}", info, line);
+                        }
                     }
-                    Connection::Server => {
-                        let mut nes = NES_PTR.lock().unwrap();
-                        let data = nes.get_pal_positions().to_owned();
-                        drop(nes);
-                        state.screen.request_redraw();
-                        if data.len() >= SPRITE_ARR_SIZE {
-                            tokio::spawn(async move {
-                                // println!("REQUEST DATA LOCK");
-                                let data_channel = DATA_CHANNEL_TX.lock().await;
-                                // println!("DATA LOCK");
-                                // println!("should send");
-                                let data_channel = match data_channel.clone() {
-                                    Some(dc) => dc,
-                                    None => {
-                                        // println!("Nos Data");
-                                        return Some((
-                                            Message::RtcEvent(RtcEvent::Waiting),
-                                            "".to_owned(),
-                                        ));
-                                    }
-                                };
-                                match data_channel
-                                    .write(&Bytes::copy_from_slice(
-                                        data[0..SPRITE_ARR_SIZE].try_into().unwrap(),
-                                    ))
-                                    .await
-                                {
-                                    Ok(_) => {
-                                        // println!("D");
-                                    }
-                                    Err(err) => {
-                                        println!("Not Sent, {}", err);
-                                    }
-                                };
-                                None
-                            });
-                        } else {
-                            println!("Invalid Length");
+                    let debugger = &nes.cpu.bus.debugger;
+                    if let Some(reason) = debugger.break_reason() {
+                        info = format!("{}\n{}", info, reason);
+                    }
+                    let breakpoints = debugger.breakpoints();
+                    if !breakpoints.is_empty() {
+                        let list: Vec<String> =
+                            breakpoints.iter().map(|b| format!("${:04X}", b)).collect();
+                        info = format!("{}\nBreakpoints: {}", info, list.join(" "));
+                    }
+                    for w in debugger.watchpoints() {
+                        info = format!(
+                            "{}\nWatch {:?} ${:04X}-${:04X}",
+                            info, w.kind, w.start, w.end
+                        );
+                    }
+                    if let Some(cart) = &nes.cart {
+                        if let Some(cdl) = cart.lock().unwrap().cdl() {
+                            let (code, data, unused) = cdl.prg_stats();
+                            info = format!(
+                                "{}\nCDL code: {} data: {} unused: {}",
+                                info, code, data, unused
+                            );
                         }
                     }
-                    Connection::Unspecified => {
-                        let mut nes = NES_PTR.lock().unwrap();
-                        let _data = nes.get_pal_positions().to_owned();
-                        drop(nes);
-                        state.screen.request_redraw();
+                    state.debug.info = info;
+                }
+                if state.started && state.connection_status != Connection::Client {
+                    match state.last_save {
+                        Some(last) if now.duration_since(last) < SAVE_INTERVAL => {}
+                        _ => {
+                            NES_PTR.lock().unwrap().save_battery_ram();
+                            state.last_save = Some(now);
+                        }
                     }
                 }
+                if state.connection_status == Connection::Client {
+                    println!("Frames: {}", state.message_count);
+                    state.message_count = 0;
+                }
             }
         }
 
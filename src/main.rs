@@ -5,22 +5,55 @@
 
 mod apu;
 mod bus;
+mod cdl;
+mod config;
 mod cartridge;
 mod cpu;
+mod debugger;
+mod dma;
+mod frame_event;
+mod gdb_stub;
 mod mapper;
 mod mapper_000;
+mod mapper_001;
+mod mapper_002;
+mod mapper_003;
+mod mapper_004;
+mod mapper_007;
+mod mapper_011;
+mod mapper_034;
+mod mapper_066;
 mod nes;
+mod palette;
 mod ppu;
+mod region;
+mod rom_info;
 mod rtc;
 mod gui;
 mod rtc_event;
 mod audio;
 mod screen;
+mod tracer;
+
+#[cfg(test)]
+mod tests;
 
 #[macro_use]
 extern crate lazy_static;
 
 #[tokio::main]
 async fn main() {
+    // Opt in to remote debugging with NES_GDB_PORT=<port>
+    if let Ok(port) = std::env::var("NES_GDB_PORT") {
+        match port.parse() {
+            Ok(port) => {
+                let nes = std::sync::Arc::clone(&nes::NES_PTR);
+                if let Err(err) = gdb_stub::GdbStub::listen(port, nes) {
+                    eprintln!("Could not start GDB stub: {}", err);
+                }
+            }
+            Err(_) => eprintln!("Invalid NES_GDB_PORT: {}", port),
+        }
+    }
     gui::MainMenu::start_program();
 }
\ No newline at end of file
@@ -1,7 +1,79 @@
-pub trait Mapper {
+use crate::cartridge::Mirror;
+use crate::mapper_000::Mapper000;
+use crate::mapper_001::Mapper001;
+use crate::mapper_002::Mapper002;
+use crate::mapper_003::Mapper003;
+use crate::mapper_004::Mapper004;
+use crate::mapper_007::Mapper007;
+use crate::mapper_011::Mapper011;
+use crate::mapper_034::Mapper034;
+use crate::mapper_066::Mapper066;
+use crate::rom_info::RomInfo;
+
+pub trait Mapper: Send {
     fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool;
-    fn cpu_map_write(&self, addr: u16, mapped_addr: &mut u32) -> bool;
+    fn cpu_map_write(&mut self, addr: u16, mapped_addr: &mut u32, data: u8) -> bool;
     fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool;
-    fn ppu_map_write(&self, addr: u16, mapped_addr: &mut u32) -> bool;
-    fn new(prg_banks: u8, chr_banks: u8) -> Self where Self : Sized;
+    fn ppu_map_write(&mut self, addr: u16, mapped_addr: &mut u32) -> bool;
+
+    /// Map $6000-$7FFF to an offset in the cartridge PRG RAM
+    fn prg_ram_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        *mapped_addr = (addr & 0x1FFF) as u32;
+        return true;
+    }
+    fn prg_ram_map_write(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        self.prg_ram_map_read(addr, mapped_addr)
+    }
+
+    /// Return registers to their power-on state
+    fn reset(&mut self) {}
+
+    /// Mirroring selected by the mapper, HARDWARE defers to the cartridge header
+    fn mirror(&self) -> Mirror {
+        Mirror::HARDWARE
+    }
+
+    /// IRQ line asserted by the mapper
+    fn irq_state(&self) -> bool {
+        false
+    }
+
+    /// Notified by the ppu when PPU A12 rises after staying low for a few M2 cycles
+    fn ppu_a12_rise(&mut self) {}
+
+    /// Notified on every cpu clock
+    fn cpu_clock(&mut self) {}
+}
+
+type MapperConstructor = fn(&RomInfo) -> Box<dyn Mapper>;
+
+// Supported mappers by iNES mapper id
+const MAPPER_REGISTRY: &[(u16, &str, MapperConstructor)] = &[
+    (0, "NROM", |info| Box::new(Mapper000::new(info.prg_banks(), info.chr_banks()))),
+    (1, "SxROM (MMC1)", |info| Box::new(Mapper001::new(info.prg_banks(), info.chr_banks()))),
+    (2, "UxROM", |info| Box::new(Mapper002::new(info.prg_banks(), info.chr_banks()))),
+    (3, "CNROM", |info| Box::new(Mapper003::new(info.prg_banks(), info.chr_banks()))),
+    (4, "TxROM (MMC3)", |info| Box::new(Mapper004::new(info.prg_banks(), info.chr_banks()))),
+    (7, "AxROM", |info| Box::new(Mapper007::new(info.prg_banks(), info.chr_banks()))),
+    (11, "Color Dreams", |info| Box::new(Mapper011::new(info.prg_banks(), info.chr_banks()))),
+    (34, "BNROM", |info| Box::new(Mapper034::new(info.prg_banks(), info.chr_banks()))),
+    (66, "GxROM", |info| Box::new(Mapper066::new(info.prg_banks(), info.chr_banks()))),
+];
+
+pub fn create_mapper(info: &RomInfo) -> Option<Box<dyn Mapper>> {
+    for (id, _, constructor) in MAPPER_REGISTRY {
+        if *id == info.mapper_id {
+            return Some(constructor(info));
+        }
+    }
+    return None;
+}
+
+pub fn mapper_name(mapper_id: u16) -> Option<&'static str> {
+    for (id, name, _) in MAPPER_REGISTRY {
+        if *id == mapper_id {
+            return Some(name);
+        }
+    }
+    return None;
 }
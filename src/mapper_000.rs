@@ -2,18 +2,21 @@ use crate::mapper::Mapper;
 
 #[derive(Default)]
 pub struct Mapper000 {
-    n_prg_banks: u8,
-    _n_chr_banks: u8,
+    n_prg_banks: usize,
+    n_chr_banks: usize,
 }
 
-impl Mapper for Mapper000 {
-    fn new(prg_banks: u8, chr_banks: u8) -> Mapper000 {
+impl Mapper000 {
+    pub fn new(prg_banks: usize, chr_banks: usize) -> Mapper000 {
         return Mapper000 {
             n_prg_banks: prg_banks,
-            _n_chr_banks: chr_banks,
+            n_chr_banks: chr_banks,
             ..Default::default()
         };
     }
+}
+
+impl Mapper for Mapper000 {
     fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
         if addr >= 0x8000 {
             if self.n_prg_banks > 1 {
@@ -25,7 +28,7 @@ impl Mapper for Mapper000 {
         }
         return false;
     }
-    fn cpu_map_write(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+    fn cpu_map_write(&mut self, addr: u16, mapped_addr: &mut u32, _data: u8) -> bool {
         self.cpu_map_read(addr, mapped_addr)
     }
     fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
@@ -36,7 +39,13 @@ impl Mapper for Mapper000 {
 
         return false;
     }
-    fn ppu_map_write(&self, addr: u16, mapped_addr: &mut u32) -> bool {
-        self.ppu_map_read(addr, mapped_addr)
+    fn ppu_map_write(&mut self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr <= 0x1FFF && self.n_chr_banks == 0 {
+            // Treat as RAM
+            *mapped_addr = addr as u32;
+            return true;
+        }
+
+        return false;
     }
 }
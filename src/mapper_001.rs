@@ -0,0 +1,179 @@
+use crate::cartridge::Mirror;
+use crate::mapper::Mapper;
+
+// MMC1: registers are loaded serially through a 5 bit shift register,
+// the fifth write commits the value to the register picked by addr bits 13-14
+pub struct Mapper001 {
+    n_prg_banks: usize,
+    n_chr_banks: usize,
+
+    load_register: u8,
+    load_register_count: u8,
+    // The serial port ignores a write on the cycle after another one, so
+    // read-modify-write instructions only shift in their first write
+    cpu_cycle: u64,
+    last_write_cycle: Option<u64>,
+
+    // Raw 5 bit register values, the banks they select depend on the
+    // modes in the control register at the time of the access
+    control_register: u8,
+    chr_register_0: u8,
+    chr_register_1: u8,
+    prg_register: u8,
+
+    mirror_mode: Mirror,
+}
+
+impl Mapper001 {
+    pub fn new(prg_banks: usize, chr_banks: usize) -> Mapper001 {
+        return Mapper001 {
+            n_prg_banks: prg_banks,
+            n_chr_banks: chr_banks,
+            load_register: 0x00,
+            load_register_count: 0x00,
+            cpu_cycle: 0,
+            last_write_cycle: None,
+            control_register: 0x1C,
+            chr_register_0: 0,
+            chr_register_1: 0,
+            prg_register: 0,
+            mirror_mode: Mirror::HARDWARE,
+        };
+    }
+
+    // Offset into CHR memory, CHR RAM wraps in the cartridge at its own size
+    fn chr_addr(&self, addr: u16) -> u32 {
+        let mapped_addr = if self.control_register & 0b10000 > 0 {
+            // 4K CHR Bank Mode
+            let bank = if addr <= 0x0FFF { self.chr_register_0 } else { self.chr_register_1 };
+            bank as u32 * 0x1000 + (addr & 0x0FFF) as u32
+        } else {
+            // 8K CHR Bank Mode, the low bit of CHR bank 0 is ignored
+            (self.chr_register_0 & 0x1E) as u32 * 0x1000 + (addr & 0x1FFF) as u32
+        };
+        if self.n_chr_banks == 0 {
+            return mapped_addr;
+        }
+        return mapped_addr % (self.n_chr_banks as u32 * 0x2000);
+    }
+}
+
+impl Mapper for Mapper001 {
+    fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr >= 0x8000 {
+            let bank = (self.prg_register & 0x0F) as usize;
+            let (lo, hi) = match (self.control_register >> 2) & 0x03 {
+                // 32K Mode, the low bit of the bank is ignored
+                0 | 1 => (bank & 0x0E, (bank & 0x0E) + 1),
+                // 16K Mode, first bank fixed at $8000
+                2 => (0, bank),
+                // 16K Mode, last bank fixed at $C000
+                _ => (bank, self.n_prg_banks.saturating_sub(1)),
+            };
+            let bank = if addr <= 0xBFFF { lo } else { hi };
+            *mapped_addr = bank as u32 * 0x4000 + (addr & 0x3FFF) as u32;
+            *mapped_addr = *mapped_addr % (self.n_prg_banks.max(1) as u32 * 0x4000);
+            return true;
+        }
+
+        return false;
+    }
+
+    fn cpu_map_write(&mut self, addr: u16, _mapped_addr: &mut u32, data: u8) -> bool {
+        if addr >= 0x8000 {
+            let consecutive = self.last_write_cycle == Some(self.cpu_cycle.wrapping_sub(1));
+            self.last_write_cycle = Some(self.cpu_cycle);
+            if consecutive {
+                return false;
+            }
+
+            if data & 0x80 > 0 {
+                // Reset the serial loading and lock PRG to fixed last bank
+                self.load_register = 0x00;
+                self.load_register_count = 0;
+                self.control_register = self.control_register | 0x0C;
+            } else {
+                self.load_register = self.load_register >> 1;
+                self.load_register = self.load_register | ((data & 0x01) << 4);
+                self.load_register_count = self.load_register_count + 1;
+
+                if self.load_register_count == 5 {
+                    let target_register = (addr >> 13) & 0x03;
+                    let value = self.load_register & 0x1F;
+
+                    match target_register {
+                        0 => {
+                            // Control register $8000-$9FFF
+                            self.control_register = value;
+                            self.mirror_mode = match self.control_register & 0x03 {
+                                0 => Mirror::ONESCREENLO,
+                                1 => Mirror::ONESCREENHI,
+                                2 => Mirror::VERTICAL,
+                                _ => Mirror::HORIZONTAL,
+                            };
+                        }
+                        // CHR bank 0 $A000-$BFFF
+                        1 => self.chr_register_0 = value,
+                        // CHR bank 1 $C000-$DFFF
+                        2 => self.chr_register_1 = value,
+                        // PRG bank $E000-$FFFF, bit 4 disables PRG RAM
+                        _ => self.prg_register = value,
+                    }
+
+                    self.load_register = 0x00;
+                    self.load_register_count = 0;
+                }
+            }
+        }
+
+        // Mapper has handled write, but do not update ROMs
+        return false;
+    }
+
+    fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 {
+            *mapped_addr = self.chr_addr(addr);
+            return true;
+        }
+
+        return false;
+    }
+
+    fn ppu_map_write(&mut self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 && self.n_chr_banks == 0 {
+            // Treat as RAM
+            *mapped_addr = self.chr_addr(addr);
+            return true;
+        }
+
+        return false;
+    }
+
+    fn prg_ram_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        *mapped_addr = (addr & 0x1FFF) as u32;
+        return self.prg_register & 0x10 == 0;
+    }
+
+    fn prg_ram_map_write(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        self.prg_ram_map_read(addr, mapped_addr)
+    }
+
+    fn reset(&mut self) {
+        self.load_register = 0x00;
+        self.load_register_count = 0x00;
+        self.last_write_cycle = None;
+        self.control_register = 0x1C;
+        self.chr_register_0 = 0;
+        self.chr_register_1 = 0;
+        self.prg_register = 0;
+        self.mirror_mode = Mirror::HARDWARE;
+    }
+
+    fn mirror(&self) -> Mirror {
+        return self.mirror_mode;
+    }
+
+    fn cpu_clock(&mut self) {
+        self.cpu_cycle = self.cpu_cycle + 1;
+    }
+}
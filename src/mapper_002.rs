@@ -0,0 +1,70 @@
+use crate::mapper::Mapper;
+
+// UxROM: switchable 16K bank at $8000, last 16K bank fixed at $C000
+#[derive(Default)]
+pub struct Mapper002 {
+    n_prg_banks: usize,
+    n_chr_banks: usize,
+    prg_bank_select_lo: usize,
+    prg_bank_select_hi: usize,
+}
+
+impl Mapper002 {
+    pub fn new(prg_banks: usize, chr_banks: usize) -> Mapper002 {
+        return Mapper002 {
+            n_prg_banks: prg_banks,
+            n_chr_banks: chr_banks,
+            prg_bank_select_lo: 0,
+            prg_bank_select_hi: prg_banks.saturating_sub(1),
+        };
+    }
+}
+
+impl Mapper for Mapper002 {
+    fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr >= 0x8000 && addr <= 0xBFFF {
+            *mapped_addr = self.prg_bank_select_lo as u32 * 0x4000 + (addr & 0x3FFF) as u32;
+            return true;
+        }
+
+        if addr >= 0xC000 {
+            *mapped_addr = self.prg_bank_select_hi as u32 * 0x4000 + (addr & 0x3FFF) as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn cpu_map_write(&mut self, addr: u16, _mapped_addr: &mut u32, data: u8) -> bool {
+        if addr >= 0x8000 {
+            self.prg_bank_select_lo = data as usize % self.n_prg_banks.max(1);
+        }
+
+        // Mapper has handled write, but do not update ROMs
+        return false;
+    }
+
+    fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 {
+            *mapped_addr = addr as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn ppu_map_write(&mut self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 && self.n_chr_banks == 0 {
+            // Treat as RAM
+            *mapped_addr = addr as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn reset(&mut self) {
+        self.prg_bank_select_lo = 0;
+        self.prg_bank_select_hi = self.n_prg_banks.saturating_sub(1);
+    }
+}
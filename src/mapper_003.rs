@@ -0,0 +1,66 @@
+use crate::mapper::Mapper;
+
+// CNROM: fixed PRG like NROM, switchable 8K CHR bank
+#[derive(Default)]
+pub struct Mapper003 {
+    n_prg_banks: usize,
+    n_chr_banks: usize,
+    chr_bank_select: usize,
+}
+
+impl Mapper003 {
+    pub fn new(prg_banks: usize, chr_banks: usize) -> Mapper003 {
+        return Mapper003 {
+            n_prg_banks: prg_banks,
+            n_chr_banks: chr_banks,
+            ..Default::default()
+        };
+    }
+}
+
+impl Mapper for Mapper003 {
+    fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr >= 0x8000 {
+            if self.n_prg_banks > 1 {
+                *mapped_addr = (addr & 0x7FFF) as u32;
+            } else {
+                *mapped_addr = (addr & 0x3FFF) as u32;
+            }
+            return true;
+        }
+
+        return false;
+    }
+
+    fn cpu_map_write(&mut self, addr: u16, _mapped_addr: &mut u32, data: u8) -> bool {
+        if addr >= 0x8000 {
+            self.chr_bank_select = data as usize % self.n_chr_banks.max(1);
+        }
+
+        // Mapper has handled write, but do not update ROMs
+        return false;
+    }
+
+    fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 {
+            *mapped_addr = self.chr_bank_select as u32 * 0x2000 + addr as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn ppu_map_write(&mut self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 && self.n_chr_banks == 0 {
+            // Treat as RAM
+            *mapped_addr = addr as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn reset(&mut self) {
+        self.chr_bank_select = 0;
+    }
+}
@@ -0,0 +1,227 @@
+use crate::cartridge::Mirror;
+use crate::mapper::Mapper;
+
+// MMC3: 8K PRG / 1K CHR banking through a bank select + bank data register
+// pair, with a scanline counter clocked by rising edges on PPU A12
+pub struct Mapper004 {
+    n_prg_banks: usize,
+    n_chr_banks: usize,
+
+    target_register: u8,
+    prg_bank_mode: bool,
+    chr_inversion: bool,
+    mirror_mode: Mirror,
+
+    registers: [u32; 8],
+    chr_bank: [u32; 8],
+    prg_bank: [u32; 4],
+
+    prg_ram_enable: bool,
+    prg_ram_write_protect: bool,
+
+    irq_active: bool,
+    irq_enable: bool,
+    irq_reload: bool,
+    irq_counter: u16,
+    irq_latch: u16,
+}
+
+impl Mapper004 {
+    pub fn new(prg_banks: usize, chr_banks: usize) -> Mapper004 {
+        let mut mapper = Mapper004 {
+            n_prg_banks: prg_banks,
+            n_chr_banks: chr_banks,
+            target_register: 0,
+            prg_bank_mode: false,
+            chr_inversion: false,
+            mirror_mode: Mirror::HARDWARE,
+            registers: [0; 8],
+            chr_bank: [0; 8],
+            prg_bank: [0; 4],
+            prg_ram_enable: true,
+            prg_ram_write_protect: false,
+            irq_active: false,
+            irq_enable: false,
+            irq_reload: false,
+            irq_counter: 0,
+            irq_latch: 0,
+        };
+        mapper.reset();
+        return mapper;
+    }
+
+    fn update_banks(&mut self) {
+        let prg_count = (self.n_prg_banks as u32 * 2).max(1);
+        // CHR RAM boards bank it the same way, the cartridge wraps at the RAM size
+        let chr_count = if self.n_chr_banks == 0 { 0x100 } else { self.n_chr_banks as u32 * 8 };
+
+        // Update CHR pointers
+        if self.chr_inversion {
+            self.chr_bank[0] = self.registers[2] % chr_count * 0x0400;
+            self.chr_bank[1] = self.registers[3] % chr_count * 0x0400;
+            self.chr_bank[2] = self.registers[4] % chr_count * 0x0400;
+            self.chr_bank[3] = self.registers[5] % chr_count * 0x0400;
+            self.chr_bank[4] = (self.registers[0] & 0xFE) % chr_count * 0x0400;
+            self.chr_bank[5] = (self.registers[0] | 0x01) % chr_count * 0x0400;
+            self.chr_bank[6] = (self.registers[1] & 0xFE) % chr_count * 0x0400;
+            self.chr_bank[7] = (self.registers[1] | 0x01) % chr_count * 0x0400;
+        } else {
+            self.chr_bank[0] = (self.registers[0] & 0xFE) % chr_count * 0x0400;
+            self.chr_bank[1] = (self.registers[0] | 0x01) % chr_count * 0x0400;
+            self.chr_bank[2] = (self.registers[1] & 0xFE) % chr_count * 0x0400;
+            self.chr_bank[3] = (self.registers[1] | 0x01) % chr_count * 0x0400;
+            self.chr_bank[4] = self.registers[2] % chr_count * 0x0400;
+            self.chr_bank[5] = self.registers[3] % chr_count * 0x0400;
+            self.chr_bank[6] = self.registers[4] % chr_count * 0x0400;
+            self.chr_bank[7] = self.registers[5] % chr_count * 0x0400;
+        }
+
+        // Update PRG pointers, the second last bank swaps with R6
+        let second_last = prg_count.saturating_sub(2) * 0x2000;
+        if self.prg_bank_mode {
+            self.prg_bank[0] = second_last;
+            self.prg_bank[2] = (self.registers[6] & 0x3F) % prg_count * 0x2000;
+        } else {
+            self.prg_bank[0] = (self.registers[6] & 0x3F) % prg_count * 0x2000;
+            self.prg_bank[2] = second_last;
+        }
+        self.prg_bank[1] = (self.registers[7] & 0x3F) % prg_count * 0x2000;
+        self.prg_bank[3] = (prg_count - 1) * 0x2000;
+    }
+}
+
+impl Mapper for Mapper004 {
+    fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr >= 0x8000 {
+            let slot = ((addr - 0x8000) / 0x2000) as usize;
+            *mapped_addr = self.prg_bank[slot] + (addr & 0x1FFF) as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn cpu_map_write(&mut self, addr: u16, _mapped_addr: &mut u32, data: u8) -> bool {
+        if addr >= 0x8000 && addr <= 0x9FFF {
+            // Bank Select / Bank Data
+            if addr & 0x0001 == 0 {
+                self.target_register = data & 0x07;
+                self.prg_bank_mode = data & 0x40 > 0;
+                self.chr_inversion = data & 0x80 > 0;
+            } else {
+                self.registers[self.target_register as usize] = data as u32;
+            }
+            self.update_banks();
+            return false;
+        }
+
+        if addr >= 0xA000 && addr <= 0xBFFF {
+            // Mirroring / PRG Ram Protect
+            if addr & 0x0001 == 0 {
+                if data & 0x01 > 0 {
+                    self.mirror_mode = Mirror::HORIZONTAL;
+                } else {
+                    self.mirror_mode = Mirror::VERTICAL;
+                }
+            } else {
+                self.prg_ram_enable = data & 0x80 > 0;
+                self.prg_ram_write_protect = data & 0x40 > 0;
+            }
+            return false;
+        }
+
+        if addr >= 0xC000 && addr <= 0xDFFF {
+            // IRQ Latch / IRQ Reload
+            if addr & 0x0001 == 0 {
+                self.irq_latch = data as u16;
+            } else {
+                self.irq_counter = 0x0000;
+                self.irq_reload = true;
+            }
+            return false;
+        }
+
+        if addr >= 0xE000 {
+            // IRQ Disable / IRQ Enable, disabling also acknowledges
+            if addr & 0x0001 == 0 {
+                self.irq_enable = false;
+                self.irq_active = false;
+            } else {
+                self.irq_enable = true;
+            }
+            return false;
+        }
+
+        return false;
+    }
+
+    fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 {
+            let slot = (addr / 0x0400) as usize;
+            *mapped_addr = self.chr_bank[slot] + (addr & 0x03FF) as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn ppu_map_write(&mut self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 && self.n_chr_banks == 0 {
+            // Treat as RAM
+            let slot = (addr / 0x0400) as usize;
+            *mapped_addr = self.chr_bank[slot] + (addr & 0x03FF) as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn prg_ram_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        *mapped_addr = (addr & 0x1FFF) as u32;
+        return self.prg_ram_enable;
+    }
+
+    fn prg_ram_map_write(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        *mapped_addr = (addr & 0x1FFF) as u32;
+        return self.prg_ram_enable && !self.prg_ram_write_protect;
+    }
+
+    fn reset(&mut self) {
+        self.target_register = 0x00;
+        self.prg_bank_mode = false;
+        self.chr_inversion = false;
+        self.mirror_mode = Mirror::HARDWARE;
+
+        self.irq_active = false;
+        self.irq_enable = false;
+        self.irq_reload = false;
+        self.irq_counter = 0x0000;
+        self.irq_latch = 0x0000;
+
+        self.registers = [0, 2, 4, 5, 6, 7, 0, 1];
+        self.update_banks();
+    }
+
+    fn mirror(&self) -> Mirror {
+        return self.mirror_mode;
+    }
+
+    fn irq_state(&self) -> bool {
+        return self.irq_active;
+    }
+
+    // With backgrounds on $0000 and sprites on $1000, A12 rises once per
+    // scanline when the sprite fetches start
+    fn ppu_a12_rise(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter = self.irq_counter - 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enable {
+            self.irq_active = true;
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::cartridge::Mirror;
+use crate::mapper::Mapper;
+
+// AxROM: switchable 32K PRG bank, CHR RAM and single screen mirroring
+pub struct Mapper007 {
+    n_prg_banks: usize,
+    n_chr_banks: usize,
+    prg_bank_select: usize,
+    mirror_mode: Mirror,
+}
+
+impl Mapper007 {
+    pub fn new(prg_banks: usize, chr_banks: usize) -> Mapper007 {
+        return Mapper007 {
+            n_prg_banks: prg_banks,
+            n_chr_banks: chr_banks,
+            prg_bank_select: 0,
+            mirror_mode: Mirror::ONESCREENLO,
+        };
+    }
+}
+
+impl Mapper for Mapper007 {
+    fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr >= 0x8000 {
+            *mapped_addr = self.prg_bank_select as u32 * 0x8000 + (addr & 0x7FFF) as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn cpu_map_write(&mut self, addr: u16, _mapped_addr: &mut u32, data: u8) -> bool {
+        if addr >= 0x8000 {
+            self.prg_bank_select = (data & 0x07) as usize % (self.n_prg_banks / 2).max(1);
+            if data & 0x10 > 0 {
+                self.mirror_mode = Mirror::ONESCREENHI;
+            } else {
+                self.mirror_mode = Mirror::ONESCREENLO;
+            }
+        }
+
+        // Mapper has handled write, but do not update ROMs
+        return false;
+    }
+
+    fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 {
+            *mapped_addr = addr as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn ppu_map_write(&mut self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 && self.n_chr_banks == 0 {
+            // Treat as RAM
+            *mapped_addr = addr as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn reset(&mut self) {
+        self.prg_bank_select = 0;
+        self.mirror_mode = Mirror::ONESCREENLO;
+    }
+
+    fn mirror(&self) -> Mirror {
+        return self.mirror_mode;
+    }
+}
@@ -0,0 +1,65 @@
+use crate::mapper::Mapper;
+
+// Color Dreams: 32K PRG bank in bits 0-1, 8K CHR bank in bits 4-7
+#[derive(Default)]
+pub struct Mapper011 {
+    n_prg_banks: usize,
+    n_chr_banks: usize,
+    prg_bank_select: usize,
+    chr_bank_select: usize,
+}
+
+impl Mapper011 {
+    pub fn new(prg_banks: usize, chr_banks: usize) -> Mapper011 {
+        return Mapper011 {
+            n_prg_banks: prg_banks,
+            n_chr_banks: chr_banks,
+            ..Default::default()
+        };
+    }
+}
+
+impl Mapper for Mapper011 {
+    fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr >= 0x8000 {
+            *mapped_addr = self.prg_bank_select as u32 * 0x8000 + (addr & 0x7FFF) as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn cpu_map_write(&mut self, addr: u16, _mapped_addr: &mut u32, data: u8) -> bool {
+        if addr >= 0x8000 {
+            self.prg_bank_select = (data & 0x03) as usize % (self.n_prg_banks / 2).max(1);
+            self.chr_bank_select = ((data & 0xF0) >> 4) as usize % self.n_chr_banks.max(1);
+        }
+
+        // Mapper has handled write, but do not update ROMs
+        return false;
+    }
+
+    fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 {
+            *mapped_addr = self.chr_bank_select as u32 * 0x2000 + addr as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn ppu_map_write(&mut self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 && self.n_chr_banks == 0 {
+            // Treat as RAM
+            *mapped_addr = addr as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn reset(&mut self) {
+        self.prg_bank_select = 0;
+        self.chr_bank_select = 0;
+    }
+}
@@ -0,0 +1,62 @@
+use crate::mapper::Mapper;
+
+// BNROM: switchable 32K PRG bank, CHR RAM
+#[derive(Default)]
+pub struct Mapper034 {
+    n_prg_banks: usize,
+    n_chr_banks: usize,
+    prg_bank_select: usize,
+}
+
+impl Mapper034 {
+    pub fn new(prg_banks: usize, chr_banks: usize) -> Mapper034 {
+        return Mapper034 {
+            n_prg_banks: prg_banks,
+            n_chr_banks: chr_banks,
+            ..Default::default()
+        };
+    }
+}
+
+impl Mapper for Mapper034 {
+    fn cpu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr >= 0x8000 {
+            *mapped_addr = self.prg_bank_select as u32 * 0x8000 + (addr & 0x7FFF) as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn cpu_map_write(&mut self, addr: u16, _mapped_addr: &mut u32, data: u8) -> bool {
+        if addr >= 0x8000 {
+            self.prg_bank_select = data as usize % (self.n_prg_banks / 2).max(1);
+        }
+
+        // Mapper has handled write, but do not update ROMs
+        return false;
+    }
+
+    fn ppu_map_read(&self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 {
+            *mapped_addr = addr as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn ppu_map_write(&mut self, addr: u16, mapped_addr: &mut u32) -> bool {
+        if addr < 0x2000 && self.n_chr_banks == 0 {
+            // Treat as RAM
+            *mapped_addr = addr as u32;
+            return true;
+        }
+
+        return false;
+    }
+
+    fn reset(&mut self) {
+        self.prg_bank_select = 0;
+    }
+}
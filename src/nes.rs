@@ -1,18 +1,35 @@
 use crate::cartridge::Cartridge;
 use crate::cpu::Cpu;
+use crate::palette::Palette;
+use crate::region::Region;
+use crate::tracer::Tracer;
+use std::path::Path;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
 use std::sync::Mutex;
 
 extern crate redis;
 use std::sync::Arc;
 // use tokio::sync::Mutex;
-// For Logging:
-// use std::io::Write;
-// use crate::util::hex;
 
 pub const SPRITE_ARR_SIZE: usize = 256 * 240;
+// RGBA bytes in a frame
+pub const FRAME_SIZE: usize = SPRITE_ARR_SIZE * 4;
+
+// Frames a subscriber can fall behind by before new ones are dropped for it
+const FRAME_QUEUE: usize = 2;
+
+// One complete picture, sent to every frame subscriber at the end of a frame
+#[derive(Debug)]
+pub struct Frame {
+    pub number: u64,
+    // 256x240 RGBA
+    pub rgba: Vec<u8>,
+    // Palette index of every pixel, what netplay streams
+    pub positions: Vec<u8>,
+}
 
 lazy_static! {
-    pub static ref NES_PTR: Arc<Mutex<Nes>> = Arc::new(Mutex::new(Nes::new("nestest.nes")));
+    pub static ref NES_PTR: Arc<Mutex<Nes>> = Arc::new(Mutex::new(Nes::new()));
 }
 
 pub struct Nes {
@@ -25,6 +42,10 @@ pub struct Nes {
     pub accumulated_time: f32,
     // pub residual_time: f32,
     pub pal_positions: Vec<u8>,
+    // RGBA picture the screen draws
+    pub frame: Vec<u8>,
+    frame_number: u64,
+    frame_listeners: Vec<SyncSender<Arc<Frame>>>,
 }
 
 //Static sound functions
@@ -39,10 +60,10 @@ pub fn sound_out(channel: u32, _global_time: f32, _time_step: f32) -> f32 {
 }
 
 impl Nes {
-    pub fn new(file: &str) -> Self {
+    pub fn new() -> Self {
         return Nes {
             cpu: Cpu::new(),
-            cart: Some(Arc::new(Mutex::new(Cartridge::new(file)))),
+            cart: None,
             emulation_run: true,
             selected_palette: 0,
             draw_mode: false,
@@ -50,40 +71,102 @@ impl Nes {
             accumulated_time: 0.0,
             // residual_time: 0.0,
             pal_positions: vec![],
+            frame: vec![],
+            frame_number: 0,
+            frame_listeners: vec![],
         };
     }
 
-    fn clock(&mut self) -> bool {
-        self.cpu.bus.get_ppu().clock();
-        self.cpu.bus.apu.clock();
-        if self.cycles % 3 == 0 {
-            if self.cpu.bus.dma_transfer {
-                if self.cpu.bus.dma_dummy {
-                    if self.cycles % 2 == 1 {
-                        self.cpu.bus.dma_dummy = false;
-                    }
-                } else {
-                    if self.cycles % 2 == 0 {
-                        let page = self.cpu.bus.dma_page;
-                        let addr = self.cpu.bus.dma_addr;
-                        let data = self.cpu.read(((page as u16) << 8) | (addr as u16), false);
-                        self.cpu.bus.dma_data = data;
-                    } else {
-                        let addr = self.cpu.bus.dma_addr;
-                        let data = self.cpu.bus.dma_data;
-                        self.cpu.bus.get_ppu().set_oam(addr as usize, data);
-                        self.cpu.bus.dma_addr = addr.wrapping_add(1);
-
-                        if self.cpu.bus.dma_addr == 0x00 {
-                            self.cpu.bus.dma_transfer = false;
-                            self.cpu.bus.dma_dummy = true;
-                        }
-                    }
+    pub fn insert_cartridge(&mut self, cart: Cartridge) {
+        self.cart = Some(Arc::new(Mutex::new(cart)));
+    }
+
+    pub fn save_battery_ram(&self) {
+        if let Some(cart) = &self.cart {
+            if let Err(err) = cart.lock().unwrap().save_battery_ram() {
+                eprintln!("Could not save battery ram: {}", err);
+            }
+        }
+    }
+
+    /// Region the cartridge header asks for, NTSC when it doesn't say
+    pub fn cart_region(&self) -> Region {
+        return match &self.cart {
+            Some(cart) => Region::from_timing(cart.lock().unwrap().rom_info.timing),
+            None => Region::NTSC,
+        };
+    }
+
+    pub fn region(&mut self) -> Region {
+        return self.cpu.bus.get_ppu().region();
+    }
+
+    /// Switch the PPU, APU and cpu clock over to the timing of another console
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu.bus.set_region(region);
+    }
+
+    /// Start or stop logging every executed instruction
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.cpu.tracer = tracer;
+    }
+
+    /// Start a code/data log of the cartridge, picking up where the file at path left off
+    pub fn start_cdl(&mut self, path: &Path) {
+        if let Some(cart) = &self.cart {
+            let started = cart.lock().unwrap().start_cdl(Some(path));
+            match started {
+                Ok(()) => self.cpu.bus.set_cdl(true),
+                Err(err) => eprintln!("Could not load {}: {}", path.display(), err),
+            }
+        }
+    }
+
+    pub fn stop_cdl(&mut self) {
+        if let Some(cart) = &self.cart {
+            cart.lock().unwrap().stop_cdl();
+        }
+        self.cpu.bus.set_cdl(false);
+    }
+
+    pub fn save_cdl(&self, path: &Path) {
+        if let Some(cart) = &self.cart {
+            if let Some(cdl) = cart.lock().unwrap().cdl() {
+                if let Err(err) = cdl.save(path) {
+                    eprintln!("Could not save {}: {}", path.display(), err);
                 }
-            } else {
-                self.cpu.clock();
             }
         }
+    }
+
+    pub fn clock(&mut self) -> bool {
+        if self.cpu.bus.debugger.is_paused() {
+            return true;
+        }
+
+        self.cpu.bus.get_ppu().clock();
+        let frame_count = self.cpu.bus.get_ppu().frame_count();
+        if frame_count != self.frame_number {
+            // A reset starts counting again, only a new count is a finished frame
+            let finished = frame_count > self.frame_number;
+            self.frame_number = frame_count;
+            if finished {
+                self.publish_frame();
+            }
+        }
+        let scanline = self.cpu.bus.get_ppu().get_scanline();
+        let cycle = self.cpu.bus.get_ppu().get_cycle();
+        self.cpu.bus.debugger.on_ppu_clock(scanline, cycle);
+        // The cpu gets cpu_cycles out of every ppu_dots clocks, spread as evenly
+        // as possible. NTSC is every third one
+        let (cpu_cycles, ppu_dots) = self.region().cpu_ppu_ratio();
+        if (self.cycles * cpu_cycles) % ppu_dots < cpu_cycles {
+            self.cpu.bus.apu.clock();
+            if let Some(cart) = &self.cart {
+                cart.lock().unwrap().cpu_clock();
+            }
+            self.cpu.clock();
+        }
 
         let mut sample_ready = false;
         self.cpu.bus.audio_time += self.cpu.bus.audio_time_per_clock;
@@ -112,8 +195,39 @@ impl Nes {
         self.cpu.bus.controller[player] = state;
     }
 
-    pub fn get_pal_positions(&mut self) -> Vec<u8> {
-        self.pal_positions = self.cpu.bus.get_ppu().pal_positions.to_vec();
-        return self.cpu.bus.get_ppu().pal_positions.to_vec();
+    /// Colors used for the PPU output, stays with this Nes until it is replaced
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.cpu.bus.get_ppu().set_palette(palette);
+    }
+
+    /// Receive frames from now on, one per vblank. A listener that falls behind misses
+    /// frames instead of queueing them. Dropping the receiver unsubscribes
+    pub fn subscribe_frames(&mut self) -> Receiver<Arc<Frame>> {
+        let (tx, rx) = sync_channel(FRAME_QUEUE);
+        self.frame_listeners.push(tx);
+        return rx;
+    }
+
+    fn publish_frame(&mut self) {
+        if self.frame_listeners.is_empty() {
+            return;
+        }
+        let frame = self.cpu.bus.get_ppu().front_frame();
+        self.frame_listeners.retain(|listener| match listener.try_send(frame.clone()) {
+            Ok(()) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+
+    /// Take palette indices streamed by the server and color them with the local palette
+    pub fn set_pal_positions(&mut self, positions: Vec<u8>) {
+        let palette = self.cpu.bus.get_ppu().palette();
+        let mut frame = Vec::with_capacity(FRAME_SIZE);
+        for index in positions.iter() {
+            let rgb = palette.rgb(*index, 0);
+            frame.extend_from_slice(&[rgb[0], rgb[1], rgb[2], 0xFF]);
+        }
+        self.frame = frame;
+        self.pal_positions = positions;
     }
 }
@@ -0,0 +1,184 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Palettes that ship with the emulator, by the name the GUI and config use
+pub const BUILTIN_PALETTES: [&str; 4] = ["2C02", "2C02 NTSC generated", "2C03 RGB", "2C07 PAL generated"];
+
+// 2C02 colors, indexed by the 6-bit value the PPU reads from palette RAM
+const NTSC_COLORS: [[u8; 3]; 64] = [
+    [84, 84, 84], [0, 30, 116], [8, 16, 144], [48, 0, 136],
+    [68, 0, 100], [92, 0, 48], [84, 4, 0], [60, 24, 0],
+    [32, 42, 0], [8, 58, 0], [0, 64, 0], [0, 60, 0],
+    [0, 50, 60], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [152, 150, 152], [8, 76, 196], [48, 50, 236], [92, 30, 228],
+    [136, 20, 176], [160, 20, 100], [152, 34, 32], [120, 60, 0],
+    [84, 90, 0], [40, 114, 0], [8, 124, 0], [0, 118, 40],
+    [0, 102, 120], [0, 0, 0], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [76, 154, 236], [120, 124, 236], [176, 98, 236],
+    [228, 84, 236], [236, 88, 180], [236, 106, 100], [212, 136, 32],
+    [160, 170, 0], [116, 196, 0], [76, 208, 32], [56, 204, 108],
+    [56, 180, 204], [60, 60, 60], [0, 0, 0], [0, 0, 0],
+    [236, 238, 236], [168, 204, 236], [188, 188, 236], [212, 178, 236],
+    [236, 174, 236], [236, 174, 212], [236, 180, 176], [228, 196, 144],
+    [204, 210, 120], [180, 222, 120], [168, 226, 144], [152, 226, 180],
+    [160, 214, 228], [160, 162, 160], [0, 0, 0], [0, 0, 0],
+];
+
+// 2C03 RGB PPU, 3 bits per channel written as octal RGB digits
+const RGB_PPU_COLORS: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420,
+    0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630,
+    0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750,
+    0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772,
+    0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+// How much an emphasis bit dims the two channels it does not select
+const EMPHASIS_ATTENUATION: f32 = 0.816328;
+
+// 64 colors for each of the 8 combinations of the PPUMASK emphasis bits, the
+// same layout as a full 1536 byte .pal file (bit 0 red, bit 1 green, bit 2 blue)
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        return Palette::from_base(&NTSC_COLORS);
+    }
+
+    /// Derive the emphasis variants of a 64 color palette
+    pub fn from_base(base: &[[u8; 3]; 64]) -> Self {
+        let mut colors = Vec::with_capacity(512);
+        for emphasis in 0..8 {
+            for i in 0..64 {
+                let mut rgb = [base[i][0] as f32, base[i][1] as f32, base[i][2] as f32];
+                // The black column is not affected
+                if i & 0x0E != 0x0E {
+                    for channel in 0..3 {
+                        if emphasis & (1 << channel) > 0 {
+                            for other in 0..3 {
+                                if other != channel {
+                                    rgb[other] = rgb[other] * EMPHASIS_ATTENUATION;
+                                }
+                            }
+                        }
+                    }
+                }
+                colors.push([rgb[0] as u8, rgb[1] as u8, rgb[2] as u8]);
+            }
+        }
+        return Palette { colors: colors };
+    }
+
+    /// One of BUILTIN_PALETTES
+    pub fn builtin(name: &str) -> Option<Palette> {
+        match name {
+            "2C02" => return Some(Palette::new()),
+            "2C02 NTSC generated" => return Some(Palette::generate(0.0)),
+            "2C03 RGB" => return Some(Palette::from_rgb_ppu(&RGB_PPU_COLORS)),
+            // The 2C07 color wave sits about 15 degrees off the NTSC one
+            "2C07 PAL generated" => return Some(Palette::generate(-0.5)),
+            _ => return None,
+        }
+    }
+
+    /// Load a .pal file, 64 colors (192 bytes) or 64 colors for each emphasis (1536 bytes)
+    pub fn load(path: &Path) -> io::Result<Palette> {
+        let data = fs::read(path)?;
+        if data.len() != 192 && data.len() != 1536 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("expected 192 or 1536 bytes but the file has {}", data.len()),
+            ));
+        }
+        let colors: Vec<[u8; 3]> = data.chunks(3).map(|c| [c[0], c[1], c[2]]).collect();
+        if colors.len() == 64 {
+            let mut base = [[0; 3]; 64];
+            base.copy_from_slice(&colors);
+            return Ok(Palette::from_base(&base));
+        }
+        return Ok(Palette { colors: colors });
+    }
+
+    // The RGB PPUs drive an emphasized channel at full strength instead of dimming the others
+    fn from_rgb_ppu(table: &[u16; 64]) -> Palette {
+        let scale = |v: u16| -> u8 { return ((v & 0x07) * 255 / 7) as u8 };
+        let mut colors = Vec::with_capacity(512);
+        for emphasis in 0..8 {
+            for i in 0..64 {
+                let mut rgb = [scale(table[i] >> 6), scale(table[i] >> 3), scale(table[i])];
+                for channel in 0..3 {
+                    if emphasis & (1 << channel) > 0 {
+                        rgb[channel] = 0xFF;
+                    }
+                }
+                colors.push(rgb);
+            }
+        }
+        return Palette { colors: colors };
+    }
+
+    // Decode the composite signal the PPU puts out for every color and emphasis, as
+    // described on the nesdev wiki (Bisqwit's generator). hue is in 30 degree steps
+    fn generate(hue: f32) -> Palette {
+        // Voltages relative to sync, the low and high half of the color wave per level
+        let levels: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+        let black = 0.518;
+        let white = 1.962;
+        let attenuation = 0.746;
+        let wave = |p: usize, color: usize| -> bool { return (color + p + 8) % 12 < 6 };
+        let gamma = |v: f32| -> u8 {
+            if v <= 0.0 {
+                return 0;
+            }
+            return (255.95 * v.powf(2.2 / 1.8)).min(255.0) as u8;
+        };
+
+        let mut colors = Vec::with_capacity(512);
+        for emphasis in 0..8 {
+            for pixel in 0..64 {
+                let color = pixel & 0x0F;
+                let mut level = (pixel >> 4) & 0x03;
+                if color >= 0x0E {
+                    level = 1;
+                }
+                let low = levels[level + if color == 0x00 { 4 } else { 0 }];
+                let high = levels[level + if color < 0x0D { 4 } else { 0 }];
+
+                let mut y = 0.0;
+                let mut i = 0.0;
+                let mut q = 0.0;
+                for p in 0..12 {
+                    let mut spot = if wave(p, color) { high } else { low };
+                    if (emphasis & 0x01 > 0 && wave(p, 0))
+                        || (emphasis & 0x02 > 0 && wave(p, 4))
+                        || (emphasis & 0x04 > 0 && wave(p, 8))
+                    {
+                        spot = spot * attenuation;
+                    }
+                    let v = (spot - black) / (white - black) / 12.0;
+                    let angle = std::f32::consts::PI / 6.0 * (p as f32 + hue);
+                    y = y + v;
+                    i = i + v * angle.cos();
+                    q = q + v * angle.sin();
+                }
+                colors.push([
+                    gamma(y + 0.946882 * i + 0.623557 * q),
+                    gamma(y - 0.274788 * i - 0.635691 * q),
+                    gamma(y - 1.108545 * i + 1.709007 * q),
+                ]);
+            }
+        }
+        return Palette { colors: colors };
+    }
+
+    /// Color of a palette RAM value drawn with PPUMASK bits 5-7 as emphasis
+    pub fn rgb(&self, index: u8, emphasis: u8) -> [u8; 3] {
+        return self.colors[((emphasis as usize & 0x07) << 6) | (index as usize & 0x3F)];
+    }
+}
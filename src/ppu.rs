@@ -1,13 +1,20 @@
 use crate::cartridge::{Cartridge, Mirror};
-use std::fs::File;
+use crate::cdl::{CDL_CHR_READ, CDL_CHR_RENDERED};
+use crate::nes::{Frame, FRAME_SIZE, SPRITE_ARR_SIZE};
+use crate::palette::Palette;
+use crate::region::Region;
 use std::sync::Arc;
 use std::sync::Mutex;
 
-// For Logging:
-// use std::io::Write;
-
 use bitfield::*;
 
+// PPU dots (about 600ms) a bit of the I/O latch holds a 1 before it fades to 0
+const IO_LATCH_DECAY: u128 = 3_221_590;
+
+// PPU A12 has to stay low for longer than this many M2 cycles before a rise
+// counts, the MMC3 ignores the short drops between the tile fetches of a scanline
+const A12_LOW_M2_CYCLES: u128 = 3;
+
 bitfield! {
     struct StatusBits(u8);
     u8;
@@ -95,11 +102,20 @@ impl Default for ObjectAttributeMemory {
 
 pub struct Ppu {
     cart: Option<Arc<Mutex<Cartridge>>>,
+    // Only lock the cartridge for pattern fetch logging while a code/data log runs
+    cdl: bool,
     _vram: [[u8; 1024]; 2],
     pub tbl_name: [[u8; 1024]; 2],
     tbl_pattern: [[u8; 4096]; 2],
     tbl_palette: [u8; 32],
-    pub pal_positions: [u8; 65535],
+    // 256x240 RGBA, palette and emphasis already applied, and the palette index of
+    // every pixel. Rendering goes to these, a finished frame is moved into front
+    frame: Vec<u8>,
+    positions: Vec<u8>,
+    front: Arc<Frame>,
+    frame_count: u64,
+    palette: Palette,
+    region: Region,
     scan_line: i16,
     cycle: i16,
     pub counter: u128,
@@ -114,6 +130,15 @@ pub struct Ppu {
     address_latch: u8,
     ppu_data_buffer: u8,
 
+    // The cpu facing data bus keeps the last value written or read, unused
+    // register bits return it. Each bit decays on its own, so keep refresh times
+    io_latch: u8,
+    io_refreshed: [u128; 8],
+
+    // Level of address line 12 on the ppu bus and when it last fell
+    a12: bool,
+    a12_fell: u128,
+
     bg_next_tile_id: u8,
     bg_next_tile_attrib: u8,
     bg_next_tile_lsb: u8,
@@ -140,18 +165,27 @@ pub struct Ppu {
 
     // debug
     pub frame_complete: bool, // tbl_pattern: [[u8; 4096]; 2], olc future
-    pub logfile: File,
 }
 
 impl Ppu {
     pub fn new() -> Self {
         let newppu = Ppu {
             cart: None,
+            cdl: false,
             _vram: [[0; 1024]; 2],
             tbl_name: [[0; 1024]; 2],
             tbl_pattern: [[0; 4096]; 2],
             tbl_palette: [0; 32],
-            pal_positions: [0; 65535],
+            frame: vec![0; FRAME_SIZE],
+            positions: vec![0; SPRITE_ARR_SIZE],
+            front: Arc::new(Frame {
+                number: 0,
+                rgba: vec![0; FRAME_SIZE],
+                positions: vec![0; SPRITE_ARR_SIZE],
+            }),
+            frame_count: 0,
+            palette: Palette::new(),
+            region: Region::NTSC,
             scan_line: 0,
             cycle: 0,
             frame_complete: false,
@@ -166,6 +200,12 @@ impl Ppu {
             address_latch: 0x00,
             ppu_data_buffer: 0x00,
 
+            io_latch: 0x00,
+            io_refreshed: [0; 8],
+
+            a12: false,
+            a12_fell: 0,
+
             bg_next_tile_id: 0x00,
             bg_next_tile_attrib: 0x00,
             bg_next_tile_lsb: 0x00,
@@ -187,13 +227,61 @@ impl Ppu {
 
             zero_hit_possible: false,
             zero_sprite_rendered: false,
-
-            logfile: File::create("ppu-log.txt").unwrap(),
         };
 
         return newppu;
     }
 
+    pub fn get_scanline(&self) -> i16 {
+        return self.scan_line;
+    }
+
+    pub fn get_cycle(&self) -> i16 {
+        return self.cycle;
+    }
+
+    /// Last complete picture, 256x240 RGBA
+    pub fn frame(&self) -> &[u8] {
+        return &self.front.rgba;
+    }
+
+    /// Last complete frame, shared rather than copied
+    pub fn front_frame(&self) -> Arc<Frame> {
+        return self.front.clone();
+    }
+
+    /// Frames completed since power on, goes up when vblank starts
+    pub fn frame_count(&self) -> u64 {
+        return self.frame_count;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        return &self.palette;
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn region(&self) -> Region {
+        return self.region;
+    }
+
+    // PPUMASK emphasis bits in palette order, bit 0 red, bit 1 green, bit 2 blue
+    fn emphasis(&self) -> u8 {
+        unsafe {
+            let bits = self.mask.reg >> 5;
+            if self.region.swaps_emphasis() {
+                return (bits & 0x04) | ((bits & 0x01) << 1) | ((bits & 0x02) >> 1);
+            }
+            return bits;
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
+
     pub fn get_oam(&self, addr: usize) -> u8 {
         unsafe {
             return self.oam[(addr / 4)].data[(addr % 4)];
@@ -208,62 +296,79 @@ impl Ppu {
 
     // Communications with cpu bus
     pub fn cpu_read(&mut self, addr: usize, rdonly: bool) -> u8 {
-        let mut data = 0x00;
+        if rdonly {
+            return self.cpu_peek(addr);
+        }
+        // Write only registers just return the latch
+        let mut data = self.io_latch_value();
         unsafe {
-            if rdonly {
-                match addr {
-                    0x0000 => {
-                        data = self.control.reg;
-                    }
-                    0x0001 => {
-                        data = self.mask.reg;
-                    }
-                    0x0002 => {
-                        data = self.status.reg;
-                    }
-                    0x0003 => {}
-                    0x0004 => {}
-                    0x0005 => {}
-                    0x0006 => {}
-                    0x0007 => {}
-                    _ => {}
+            match addr {
+                0x0000 => {}
+                0x0001 => {}
+                0x0002 => {
+                    // Status register, the low bits are whatever is left on the bus
+                    data = (self.status.reg & 0xE0) | (data & 0x1F);
+                    self.io_latch_refresh(data, 0xE0);
+                    self.status.bits.set_vertical_blank(false);
+                    self.address_latch = 0;
                 }
-            } else {
-                match addr {
-                    0x0000 => {}
-                    0x0001 => {}
-                    0x0002 => {
-                        // Status register
-                        data = (self.status.reg & 0xE0) | (self.ppu_data_buffer & 0x1F);
-                        self.status.bits.set_vertical_blank(false);
-                        self.address_latch = 0;
-                    }
-                    0x0003 => {}
-                    0x0004 => {
-                        data = self.get_oam(self.oam_addr as usize);
+                0x0003 => {}
+                0x0004 => {
+                    data = self.get_oam(self.oam_addr as usize);
+                    self.io_latch_refresh(data, 0xFF);
+                }
+                0x0005 => {}
+                0x0006 => {}
+                0x0007 => {
+                    data = self.ppu_data_buffer;
+                    self.watch_a12(self.vram_addr.reg);
+                    self.cdl_log_chr(self.vram_addr.reg, CDL_CHR_READ);
+                    self.ppu_data_buffer = self.ppu_read(self.vram_addr.reg);
+                    if self.vram_addr.reg >= 0x3F00 {
+                        // Palette entries are 6 bits, the top two come from the bus
+                        data = (self.ppu_data_buffer & 0x3F) | (self.io_latch_value() & 0xC0);
+                        self.io_latch_refresh(data, 0x3F);
+                    } else {
+                        self.io_latch_refresh(data, 0xFF);
                     }
-                    0x0005 => {}
-                    0x0006 => {}
-                    0x0007 => {
-                        data = self.ppu_data_buffer;
-                        self.ppu_data_buffer = self.ppu_read(self.vram_addr.reg, false);
-                        if self.vram_addr.reg >= 0x3F00 {
-                            data = self.ppu_data_buffer;
-                        }
-                        if self.control.bits.increment_mode() {
-                            self.vram_addr.reg = self.vram_addr.reg.wrapping_add(32);
-                        } else {
-                            self.vram_addr.reg = self.vram_addr.reg.wrapping_add(1);
-                        }
+                    if self.control.bits.increment_mode() {
+                        self.vram_addr.reg = self.vram_addr.reg.wrapping_add(32);
+                    } else {
+                        self.vram_addr.reg = self.vram_addr.reg.wrapping_add(1);
                     }
-                    _ => {}
                 }
+                _ => {}
             }
         }
         return data;
     }
 
+    /// What a cpu read would return, leaving the latch, vblank flag and read buffer alone
+    pub fn cpu_peek(&self, addr: usize) -> u8 {
+        unsafe {
+            match addr {
+                0x0002 => {
+                    return (self.status.reg & 0xE0) | (self.io_latch_value() & 0x1F);
+                }
+                0x0004 => {
+                    return self.get_oam(self.oam_addr as usize);
+                }
+                0x0007 => {
+                    if self.vram_addr.reg >= 0x3F00 {
+                        return (self.ppu_read(self.vram_addr.reg) & 0x3F)
+                            | (self.io_latch_value() & 0xC0);
+                    }
+                    return self.ppu_data_buffer;
+                }
+                _ => {
+                    return self.io_latch_value();
+                }
+            }
+        }
+    }
+
     pub fn cpu_write(&mut self, addr: usize, data: u8) {
+        self.io_latch_refresh(data, 0xFF);
         unsafe {
             match addr {
                 0x0000 => {
@@ -305,9 +410,11 @@ impl Ppu {
                         self.tram_addr.reg = (self.tram_addr.reg & 0xFF00) | data as u16;
                         self.vram_addr.reg = self.tram_addr.reg;
                         self.address_latch = 0;
+                        self.watch_a12(self.vram_addr.reg);
                     }
                 }
                 0x0007 => {
+                    self.watch_a12(self.vram_addr.reg);
                     self.ppu_write(self.vram_addr.reg, data);
                     if self.control.bits.increment_mode() {
                         self.vram_addr.reg = self.vram_addr.reg.wrapping_add(32);
@@ -320,19 +427,95 @@ impl Ppu {
         }
     }
 
+    // Latch with the bits that have not been driven for too long faded to 0
+    fn io_latch_value(&self) -> u8 {
+        let mut data = self.io_latch;
+        for bit in 0..8 {
+            if self.counter - self.io_refreshed[bit] > IO_LATCH_DECAY {
+                data = data & !(1 << bit);
+            }
+        }
+        return data;
+    }
+
+    // Drive the bits in mask onto the latch
+    fn io_latch_refresh(&mut self, data: u8, mask: u8) {
+        self.io_latch = (self.io_latch & !mask) | (data & mask);
+        for bit in 0..8 {
+            if mask & (1 << bit) > 0 {
+                self.io_refreshed[bit] = self.counter;
+            }
+        }
+    }
+
+    // Fetches made while rendering put their address on the ppu bus
+    fn ppu_fetch(&mut self, addr: u16) -> u8 {
+        if self.rendering() {
+            self.watch_a12(addr);
+        }
+        return self.ppu_read(addr);
+    }
+
+    // Pattern fetches made while rendering, the code/data log counts these as drawn
+    fn ppu_read_pattern(&mut self, addr: u16) -> u8 {
+        let data = self.ppu_fetch(addr);
+        self.cdl_log_chr(addr, CDL_CHR_RENDERED);
+        return data;
+    }
+
+    fn rendering(&self) -> bool {
+        unsafe {
+            return self.mask.bits.render_background() || self.mask.bits.render_sprites();
+        }
+    }
+
+    // Track PPU A12 and tell the mapper about every rise that follows a long
+    // enough low period, which is what the MMC3 counts scanlines with
+    fn watch_a12(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 > 0;
+        if a12 && !self.a12 {
+            let (cpu_cycles, ppu_dots) = self.region.cpu_ppu_ratio();
+            let low_dots = self.counter - self.a12_fell;
+            if low_dots * cpu_cycles > A12_LOW_M2_CYCLES * ppu_dots {
+                if let Some(cart) = &self.cart {
+                    cart.lock().unwrap().ppu_a12_rise();
+                }
+            }
+        } else if !a12 && self.a12 {
+            self.a12_fell = self.counter;
+        }
+        self.a12 = a12;
+    }
+
+    fn cdl_log_chr(&self, addr: u16, flags: u8) {
+        if !self.cdl {
+            return;
+        }
+        if let Some(cart) = &self.cart {
+            cart.lock().unwrap().cdl_log_chr(addr & 0x3FFF, flags);
+        }
+    }
+
     // Communications with ppu bus
-    pub fn ppu_read(&self, mut addr: u16, _: bool) -> u8 {
+    pub fn ppu_read(&self, mut addr: u16) -> u8 {
         let mut data = 0x00;
         addr = (addr as u16) & 0x3FFF;
-        let cart = self.cart.as_ref().unwrap().lock().unwrap();
+        // Without a cartridge the PPU's own memory answers everything
+        let (mapped, mirror) = match &self.cart {
+            Some(cart) => {
+                let cart = cart.lock().unwrap();
+                (cart.ppu_read(addr, &mut data), cart.mirror())
+            }
+            None => (false, Mirror::HARDWARE),
+        };
 
-        if cart.ppu_read(addr, &mut data) {
+        if mapped {
         } else if addr <= 0x1FFF {
             data = self.tbl_pattern[((addr & 0x1000) >> 12) as usize][(addr & 0x0FFF) as usize];
         } else if addr <= 0x3EFF {
             addr = addr & 0x0FFF;
             let masked_addr = (addr & 0x03FF) as usize;
-            match cart.mirror {
+            match mirror {
                 Mirror::VERTICAL => {
                     if addr <= 0x03FF {
                         data = self.tbl_name[0][masked_addr];
@@ -355,6 +538,12 @@ impl Ppu {
                         data = self.tbl_name[1][masked_addr];
                     }
                 }
+                Mirror::ONESCREENLO | Mirror::HARDWARE => {
+                    data = self.tbl_name[0][masked_addr];
+                }
+                Mirror::ONESCREENHI => {
+                    data = self.tbl_name[1][masked_addr];
+                }
             }
         } else if addr <= 0x3FFF {
             addr = addr & 0x001F;
@@ -386,15 +575,21 @@ impl Ppu {
     }
     pub fn ppu_write(&mut self, mut addr: u16, data: u8) {
         addr = (addr as u16) & 0x3FFF;
-        let mut cart = self.cart.as_ref().unwrap().lock().unwrap();
+        let (mapped, mirror) = match &self.cart {
+            Some(cart) => {
+                let mut cart = cart.lock().unwrap();
+                (cart.ppu_write(addr as usize, data), cart.mirror())
+            }
+            None => (false, Mirror::HARDWARE),
+        };
 
-        if (*cart).ppu_write(addr as usize, data) {
+        if mapped {
         } else if addr <= 0x1FFF {
             self.tbl_pattern[((addr & 0x1000) >> 12) as usize][(addr & 0x0FFF) as usize] = data;
         } else if addr <= 0x3EFF {
             addr = addr & 0x0FFF;
             let masked_addr = (addr & 0x03FF) as usize;
-            match cart.mirror {
+            match mirror {
                 Mirror::VERTICAL => {
                     if addr <= 0x03FF {
                         self.tbl_name[0][masked_addr] = data;
@@ -417,6 +612,12 @@ impl Ppu {
                         self.tbl_name[1][masked_addr] = data;
                     }
                 }
+                Mirror::ONESCREENLO | Mirror::HARDWARE => {
+                    self.tbl_name[0][masked_addr] = data;
+                }
+                Mirror::ONESCREENHI => {
+                    self.tbl_name[1][masked_addr] = data;
+                }
             }
         } else if addr <= 0x3FFF {
             addr = addr & 0x001F;
@@ -443,12 +644,16 @@ impl Ppu {
         self.cart = Some(Arc::clone(&cart));
     }
 
+    pub fn set_cdl(&mut self, enabled: bool) {
+        self.cdl = enabled;
+    }
+
     // pub fn get_decal(&self) -> &Decal {
     //     &Decal::create(Some(*self.spr_screen.deref()))
     // }
 
     pub fn get_pal_position(&self, palette: u8, pixel: u8) -> usize {
-        let i = self.ppu_read(0x3F00 + ((palette as u16) << 2) + (pixel as u16), false);
+        let i = self.ppu_read(0x3F00 + ((palette as u16) << 2) + (pixel as u16));
         return (i & 0x3F) as usize;
     }
 
@@ -533,6 +738,59 @@ impl Ppu {
         }
     }
 
+    // Address of the low pattern byte slot i fetches for the next scanline
+    unsafe fn sprite_pattern_addr(&self, i: usize) -> u16 {
+        if i >= self.sprite_count as usize {
+            if self.control.bits.sprite_size() {
+                return 0x1FE0;
+            }
+            return ((self.control.bits.pattern_sprite() as u16) << 12) | 0x0FF0;
+        }
+
+        let sprite_addr_lo: u16;
+        let current_sprite = self.sprite_scanline[i];
+
+        if !self.control.bits.sprite_size() {
+            if current_sprite.map.attribute & 0x80 == 0 {
+                sprite_addr_lo = ((self.control.bits.pattern_sprite() as u16) << 12)
+                    | ((current_sprite.map.id as u16) << 4)
+                    | (self.scan_line as u16).wrapping_sub(current_sprite.map.y as u16);
+            } else {
+                sprite_addr_lo = ((self.control.bits.pattern_sprite() as u16) << 12)
+                    | ((current_sprite.map.id as u16) << 4)
+                    | ((7 as u16).wrapping_sub(
+                        (self.scan_line as u16).wrapping_sub(current_sprite.map.y as u16),
+                    ));
+            }
+        } else {
+            if current_sprite.map.attribute & 0x80 == 0 {
+                if self.scan_line.wrapping_sub(current_sprite.map.y as i16) < 8 {
+                    sprite_addr_lo = (((current_sprite.map.id & 0x01) as u16) << 12)
+                        | (((current_sprite.map.id & 0xFE) as u16) << 4)
+                        | ((self.scan_line as u16).wrapping_sub(current_sprite.map.y as u16)
+                            & 0x07);
+                } else {
+                    sprite_addr_lo = (((current_sprite.map.id & 0x01) as u16) << 12)
+                        | ((((current_sprite.map.id & 0xFE) + 1) as u16) << 4)
+                        | ((self.scan_line as u16).wrapping_sub(current_sprite.map.y as u16)
+                            & 0x07);
+                }
+            } else {
+                if self.scan_line.wrapping_sub(current_sprite.map.y as i16) < 8 {
+                    sprite_addr_lo = (((current_sprite.map.id & 0x01) as u16) << 12)
+                        | ((((current_sprite.map.id & 0xFE) + 1) as u16) << 4)
+                        | (7 - (((self.scan_line as u16) - (current_sprite.map.y as u16)) & 0x07));
+                } else {
+                    sprite_addr_lo = (((current_sprite.map.id & 0x01) as u16) << 12)
+                        | (((current_sprite.map.id & 0xFE) as u16) << 4)
+                        | (7 - (((self.scan_line as u16) - (current_sprite.map.y as u16)) & 0x07));
+                }
+            }
+        }
+
+        return sprite_addr_lo;
+    }
+
     unsafe fn update_shifters(&mut self) {
         if self.mask.bits.render_background() {
             self.bg_shifter_pattern_lo = self.bg_shifter_pattern_lo << 1;
@@ -560,7 +818,7 @@ impl Ppu {
     pub fn clock(&mut self) -> u128 {
         unsafe {
             if self.scan_line >= -1 && self.scan_line < 240 {
-                if self.scan_line == 0 && self.cycle == 0 {
+                if self.scan_line == 0 && self.cycle == 0 && self.region.skips_dot() {
                     self.cycle = 1;
                 }
                 if self.scan_line == -1 && self.cycle == 1 {
@@ -581,16 +839,15 @@ impl Ppu {
                         0 => {
                             self.load_background_shifters();
                             self.bg_next_tile_id =
-                                self.ppu_read(0x2000 | (self.vram_addr.reg & 0x0FFF), false);
+                                self.ppu_fetch(0x2000 | (self.vram_addr.reg & 0x0FFF));
                         }
                         2 => {
-                            self.bg_next_tile_attrib = self.ppu_read(
+                            self.bg_next_tile_attrib = self.ppu_fetch(
                                 0x23C0
                                     | ((self.vram_addr.bits.nametable_y() as u16) << 11)
                                     | ((self.vram_addr.bits.nametable_x() as u16) << 10)
                                     | (((self.vram_addr.bits.coarse_y() as u16) >> 2) << 3)
                                     | ((self.vram_addr.bits.coarse_x() as u16) >> 2),
-                                false,
                             );
                             if (self.vram_addr.bits.coarse_y() as u16) & 0x02 > 0 {
                                 self.bg_next_tile_attrib = self.bg_next_tile_attrib >> 4;
@@ -601,21 +858,19 @@ impl Ppu {
                             self.bg_next_tile_attrib = self.bg_next_tile_attrib & 0x03;
                         }
                         4 => {
-                            self.bg_next_tile_lsb = self.ppu_read(
+                            self.bg_next_tile_lsb = self.ppu_read_pattern(
                                 ((self.control.bits.pattern_background() as u16) << 12)
                                     + ((self.bg_next_tile_id as u16) << 4)
                                     + (self.vram_addr.bits.fine_y() as u16)
                                     + 0,
-                                false,
                             );
                         }
                         6 => {
-                            self.bg_next_tile_msb = self.ppu_read(
+                            self.bg_next_tile_msb = self.ppu_read_pattern(
                                 ((self.control.bits.pattern_background() as u16) << 12)
                                     + ((self.bg_next_tile_id as u16) << 4)
                                     + (self.vram_addr.bits.fine_y() as u16)
                                     + 8,
-                                false,
                             );
                         }
                         7 => {
@@ -636,7 +891,7 @@ impl Ppu {
 
                 if self.cycle == 338 || self.cycle == 340 {
                     self.bg_next_tile_id =
-                        self.ppu_read(0x2000 | (self.vram_addr.reg & 0x0FFF), false);
+                        self.ppu_fetch(0x2000 | (self.vram_addr.reg & 0x0FFF));
                 }
 
                 if self.scan_line == -1 && self.cycle >= 280 && self.cycle < 305 {
@@ -644,6 +899,11 @@ impl Ppu {
                 }
 
                 //Foreground Rendering
+                if self.cycle == 257 && self.scan_line == -1 {
+                    // Nothing is evaluated for the first line, only dummy fetches happen
+                    self.sprite_count = 0;
+                }
+
                 if self.cycle == 257 && self.scan_line >= 0 {
                     self.sprite_scanline = [ObjectAttributeMemory::default(); 8];
                     self.sprite_count = 0;
@@ -679,96 +939,57 @@ impl Ppu {
                     self.status.bits.set_sprite_overflow(self.sprite_count > 8);
                 }
 
-                if self.cycle == 340 {
-                    for i in 0..self.sprite_count {
-                        let mut sprite_bits_lo: u8;
-                        let mut sprite_bits_hi: u8;
-                        let sprite_addr_lo: u16;
-                        let sprite_addr_hi: u16;
-
-                        let current_sprite = self.sprite_scanline[i as usize];
-
-                        if !self.control.bits.sprite_size() {
-                            if current_sprite.map.attribute & 0x80 == 0 {
-                                sprite_addr_lo = ((self.control.bits.pattern_sprite() as u16)
-                                    << 12)
-                                    | ((current_sprite.map.id as u16) << 4)
-                                    | (self.scan_line as u16)
-                                        .wrapping_sub(current_sprite.map.y as u16);
-                            } else {
-                                sprite_addr_lo = ((self.control.bits.pattern_sprite() as u16)
-                                    << 12)
-                                    | ((current_sprite.map.id as u16) << 4)
-                                    | ((7 as u16).wrapping_sub(
-                                        (self.scan_line as u16)
-                                            .wrapping_sub(current_sprite.map.y as u16),
-                                    ));
-                            }
-                        } else {
-                            if current_sprite.map.attribute & 0x80 == 0 {
-                                if self.scan_line.wrapping_sub(current_sprite.map.y as i16) < 8 {
-                                    sprite_addr_lo = (((current_sprite.map.id & 0x01) as u16)
-                                        << 12)
-                                        | (((current_sprite.map.id & 0xFE) as u16) << 4)
-                                        | ((self.scan_line as u16)
-                                            .wrapping_sub(current_sprite.map.y as u16)
-                                            & 0x07);
-                                } else {
-                                    sprite_addr_lo = (((current_sprite.map.id & 0x01) as u16)
-                                        << 12)
-                                        | ((((current_sprite.map.id & 0xFE) + 1) as u16) << 4)
-                                        | ((self.scan_line as u16).wrapping_sub(
-                                            self.sprite_scanline[i as usize].map.y as u16,
-                                        ) & 0x07);
-                                }
-                            } else {
-                                if self.scan_line.wrapping_sub(current_sprite.map.y as i16) < 8 {
-                                    sprite_addr_lo = (((current_sprite.map.id & 0x01) as u16)
-                                        << 12)
-                                        | ((((current_sprite.map.id & 0xFE) + 1) as u16) << 4)
-                                        | (7 - (((self.scan_line as u16)
-                                            - (current_sprite.map.y as u16))
-                                            & 0x07));
-                                } else {
-                                    sprite_addr_lo = (((current_sprite.map.id & 0x01) as u16)
-                                        << 12)
-                                        | (((current_sprite.map.id & 0xFE) as u16) << 4)
-                                        | (7 - (((self.scan_line as u16)
-                                            - (current_sprite.map.y as u16))
-                                            & 0x07));
-                                }
-                            }
+                // Sprite patterns are fetched one slot per 8 cycles between the
+                // nametable fetches, empty slots fetch tile $FF
+                if self.cycle >= 257 && self.cycle < 321 {
+                    let i = ((self.cycle - 257) / 8) as usize;
+                    match (self.cycle - 1) % 8 {
+                        0 | 2 => {
+                            self.ppu_fetch(0x2000 | (self.vram_addr.reg & 0x0FFF));
                         }
-
-                        sprite_addr_hi = sprite_addr_lo.wrapping_add(8);
-                        sprite_bits_lo = self.ppu_read(sprite_addr_lo, false);
-                        sprite_bits_hi = self.ppu_read(sprite_addr_hi, false);
-
-                        if current_sprite.map.attribute & 0x40 > 0 {
-                            let flipbyte = |mut b: u8| -> u8 {
-                                b = ((b & 0xF0) >> 4) | ((b & 0x0F) << 4);
-                                b = ((b & 0xCC) >> 2) | ((b & 0x33) << 2);
-                                b = ((b & 0xAA) >> 1) | ((b & 0x55) << 1);
-                                return b;
-                            };
-                            sprite_bits_lo = flipbyte(sprite_bits_lo);
-                            sprite_bits_hi = flipbyte(sprite_bits_hi);
+                        4 => {
+                            let sprite_addr = self.sprite_pattern_addr(i);
+                            self.sprite_shifter_pattern_lo[i] = self.ppu_read_pattern(sprite_addr);
                         }
+                        6 => {
+                            let sprite_addr = self.sprite_pattern_addr(i).wrapping_add(8);
+                            let mut sprite_bits_lo = self.sprite_shifter_pattern_lo[i];
+                            let mut sprite_bits_hi = self.ppu_read_pattern(sprite_addr);
+
+                            if i >= self.sprite_count as usize {
+                                sprite_bits_lo = 0;
+                                sprite_bits_hi = 0;
+                            } else if self.sprite_scanline[i].map.attribute & 0x40 > 0 {
+                                let flipbyte = |mut b: u8| -> u8 {
+                                    b = ((b & 0xF0) >> 4) | ((b & 0x0F) << 4);
+                                    b = ((b & 0xCC) >> 2) | ((b & 0x33) << 2);
+                                    b = ((b & 0xAA) >> 1) | ((b & 0x55) << 1);
+                                    return b;
+                                };
+                                sprite_bits_lo = flipbyte(sprite_bits_lo);
+                                sprite_bits_hi = flipbyte(sprite_bits_hi);
+                            }
 
-                        self.sprite_shifter_pattern_lo[i as usize] = sprite_bits_lo;
-                        self.sprite_shifter_pattern_hi[i as usize] = sprite_bits_hi;
+                            self.sprite_shifter_pattern_lo[i] = sprite_bits_lo;
+                            self.sprite_shifter_pattern_hi[i] = sprite_bits_hi;
+                        }
+                        _ => {}
                     }
                 }
             }
 
             if self.scan_line == 240 {}
 
-            if self.scan_line >= 241 && self.scan_line < 261 {
-                if self.scan_line == 241 && self.cycle == 1 {
+            if self.scan_line >= 241 && self.scan_line < self.region.scanlines() - 1 {
+                if self.scan_line == self.region.vblank_line() && self.cycle == 1 {
                     self.status.bits.set_vertical_blank(true);
                     if self.control.bits.enable_nmi() {
                         self.nmi = true;
                     }
+                    // The picture is done, deliver it along with the nmi
+                    self.frame_complete = true;
+                    self.frame_count = self.frame_count + 1;
+                    self.finish_frame();
                 }
             }
 
@@ -858,9 +1079,10 @@ impl Ppu {
             let spry = self.scan_line;
             if sprx >= 0 && (sprx as i32) < 256 as i32 && spry >= 0 && (spry as i32) < 240 as i32 {
                 let cpa = self.get_pal_position(palette, pixel);
-                self.pal_positions
-                    [(spry as i32 * 256 as i32 + sprx as i32) as usize] =
-                    cpa as u8;
+                let pos = (spry as i32 * 256 as i32 + sprx as i32) as usize;
+                self.positions[pos] = cpa as u8;
+                let rgb = self.palette.rgb(cpa as u8, self.emphasis());
+                self.frame[pos * 4..pos * 4 + 4].copy_from_slice(&[rgb[0], rgb[1], rgb[2], 0xFF]);
             }
         }
 
@@ -868,9 +1090,8 @@ impl Ppu {
         if self.cycle >= 341 {
             self.cycle = 0;
             self.scan_line = self.scan_line + 1;
-            if self.scan_line >= 261 {
+            if self.scan_line >= self.region.scanlines() - 1 {
                 self.scan_line = -1;
-                self.frame_complete = true;
             }
         }
 
@@ -878,11 +1099,22 @@ impl Ppu {
         return self.counter;
     }
 
+    // Hand the finished buffers over to front and render the next frame into new ones,
+    // every visible dot is drawn again so they need no clearing
+    fn finish_frame(&mut self) {
+        self.front = Arc::new(Frame {
+            number: self.frame_count,
+            rgba: std::mem::replace(&mut self.frame, vec![0; FRAME_SIZE]),
+            positions: std::mem::replace(&mut self.positions, vec![0; SPRITE_ARR_SIZE]),
+        });
+    }
+
     pub fn reset(&mut self) {
         println!("PPU Reset Start");
         self.fine_x = 0x00;
         self.address_latch = 0x00;
         self.ppu_data_buffer = 0x00;
+        self.io_latch = 0x00;
         self.scan_line = 0;
         self.cycle = 0;
         self.bg_next_tile_id = 0x00;
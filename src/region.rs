@@ -0,0 +1,104 @@
+use crate::rom_info::TimingMode;
+
+// Console timing, the cartridge header says which one a game was made for
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Region {
+    NTSC,
+    PAL,
+    DENDY,
+}
+
+pub const REGION_NAMES: [&str; 3] = ["NTSC", "PAL", "Dendy"];
+
+impl Default for Region {
+    fn default() -> Self {
+        Region::NTSC
+    }
+}
+
+impl Region {
+    // Multi-region games run fine on NTSC
+    pub fn from_timing(timing: TimingMode) -> Region {
+        return match timing {
+            TimingMode::PAL => Region::PAL,
+            TimingMode::DENDY => Region::DENDY,
+            _ => Region::NTSC,
+        };
+    }
+
+    pub fn from_name(name: &str) -> Option<Region> {
+        return match name {
+            "NTSC" => Some(Region::NTSC),
+            "PAL" => Some(Region::PAL),
+            "Dendy" => Some(Region::DENDY),
+            _ => None,
+        };
+    }
+
+    pub fn name(&self) -> &'static str {
+        return match self {
+            Region::NTSC => REGION_NAMES[0],
+            Region::PAL => REGION_NAMES[1],
+            Region::DENDY => REGION_NAMES[2],
+        };
+    }
+
+    /// Scanlines per frame, counting the pre-render line
+    pub fn scanlines(&self) -> i16 {
+        return match self {
+            Region::NTSC => 262,
+            Region::PAL | Region::DENDY => 312,
+        };
+    }
+
+    /// Scanline the vertical blank and NMI start on. Dendy keeps the NTSC
+    /// vblank length and pads the extra lines before it instead
+    pub fn vblank_line(&self) -> i16 {
+        return match self {
+            Region::NTSC | Region::PAL => 241,
+            Region::DENDY => 291,
+        };
+    }
+
+    /// The 2C07 and the Dendy PPUs swap the red and green emphasis bits of PPUMASK
+    pub fn swaps_emphasis(&self) -> bool {
+        return *self != Region::NTSC;
+    }
+
+    /// Only the NTSC PPU drops a dot from the pre-render line
+    pub fn skips_dot(&self) -> bool {
+        return *self == Region::NTSC;
+    }
+
+    /// Width of a pixel over its height on a TV. The PAL and Dendy PPUs share the
+    /// same dot clock, so their pixels come out wider than NTSC's 8:7
+    pub fn pixel_aspect(&self) -> f32 {
+        return match self {
+            Region::NTSC => 8.0 / 7.0,
+            Region::PAL | Region::DENDY => 2950000.0 / 2128137.0,
+        };
+    }
+
+    /// (cpu cycles, ppu dots) that take the same time
+    pub fn cpu_ppu_ratio(&self) -> (u128, u128) {
+        return match self {
+            Region::NTSC | Region::DENDY => (1, 3),
+            Region::PAL => (5, 16),
+        };
+    }
+
+    pub fn cpu_clock_rate(&self) -> f64 {
+        return match self {
+            Region::NTSC => 1789773.0,
+            Region::PAL => 1662607.0,
+            Region::DENDY => 1773448.0,
+        };
+    }
+
+    pub fn ppu_clock_rate(&self) -> f64 {
+        return match self {
+            Region::NTSC => 5369318.0,
+            Region::PAL | Region::DENDY => 5320342.5,
+        };
+    }
+}
@@ -0,0 +1,220 @@
+use crate::cartridge::Mirror;
+use crate::mapper::mapper_name;
+use std::fmt;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum HeaderFormat {
+    INES,
+    NES20,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum TimingMode {
+    NTSC,
+    PAL,
+    MULTIPLE,
+    DENDY,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ConsoleType {
+    NES,
+    VSSYSTEM,
+    PLAYCHOICE,
+    EXTENDED(u8),
+}
+
+// Everything the 16 byte iNES / NES 2.0 header tells us about the cartridge
+#[derive(Clone)]
+pub struct RomInfo {
+    pub format: HeaderFormat,
+    pub mapper_id: u16,
+    pub submapper_id: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirror: Mirror,
+    pub battery: bool,
+    pub trainer: bool,
+    pub timing: TimingMode,
+    pub console: ConsoleType,
+}
+
+impl Default for RomInfo {
+    fn default() -> Self {
+        RomInfo {
+            format: HeaderFormat::INES,
+            mapper_id: 0,
+            submapper_id: 0,
+            prg_rom_size: 0,
+            chr_rom_size: 0,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            mirror: Mirror::HORIZONTAL,
+            battery: false,
+            trainer: false,
+            timing: TimingMode::NTSC,
+            console: ConsoleType::NES,
+        }
+    }
+}
+
+impl RomInfo {
+    pub fn parse(header: &[u8; 16]) -> RomInfo {
+        let mut info = RomInfo::default();
+
+        info.mirror = if header[6] & 0x01 > 0 {
+            Mirror::VERTICAL
+        } else {
+            Mirror::HORIZONTAL
+        };
+        info.battery = header[6] & 0x02 > 0;
+        info.trainer = header[6] & 0x04 > 0;
+        // Bit 3 asks for four-screen nametable RAM on the cartridge, which is not emulated
+
+        info.console = match header[7] & 0x03 {
+            0 => ConsoleType::NES,
+            1 => ConsoleType::VSSYSTEM,
+            2 => ConsoleType::PLAYCHOICE,
+            _ => ConsoleType::EXTENDED(header[13] & 0x0F),
+        };
+
+        if header[7] & 0x0C == 0x08 {
+            info.format = HeaderFormat::NES20;
+            info.mapper_id = (((header[8] & 0x0F) as u16) << 8)
+                | ((header[7] & 0xF0) as u16)
+                | ((header[6] >> 4) as u16);
+            info.submapper_id = header[8] >> 4;
+
+            info.prg_rom_size = Self::rom_size(header[4], header[9] & 0x0F, 16 * 1024);
+            info.chr_rom_size = Self::rom_size(header[5], header[9] >> 4, 8 * 1024);
+
+            info.prg_ram_size = Self::ram_size(header[10] & 0x0F);
+            info.prg_nvram_size = Self::ram_size(header[10] >> 4);
+            info.chr_ram_size = Self::ram_size(header[11] & 0x0F);
+            info.chr_nvram_size = Self::ram_size(header[11] >> 4);
+
+            info.timing = match header[12] & 0x03 {
+                0 => TimingMode::NTSC,
+                1 => TimingMode::PAL,
+                2 => TimingMode::MULTIPLE,
+                _ => TimingMode::DENDY,
+            };
+        } else {
+            info.format = HeaderFormat::INES;
+            // Old dumps have garbage in bytes 12-15, only trust the low nibble then
+            let dirty = header[12..16].iter().any(|b| *b != 0);
+            info.mapper_id = (header[6] >> 4) as u16;
+            if !dirty {
+                info.mapper_id = info.mapper_id | ((header[7] & 0xF0) as u16);
+            } else {
+                info.console = ConsoleType::NES;
+            }
+
+            info.prg_rom_size = header[4] as usize * 16 * 1024;
+            info.chr_rom_size = header[5] as usize * 8 * 1024;
+
+            // A value of 0 infers 8KB for compatibility
+            let prg_ram = (header[8].max(1)) as usize * 8 * 1024;
+            if info.battery {
+                info.prg_nvram_size = prg_ram;
+            } else {
+                info.prg_ram_size = prg_ram;
+            }
+            if info.chr_rom_size == 0 {
+                info.chr_ram_size = 8 * 1024;
+            }
+
+            info.timing = if !dirty && header[9] & 0x01 > 0 {
+                TimingMode::PAL
+            } else {
+                TimingMode::NTSC
+            };
+        }
+
+        return info;
+    }
+
+    // NES 2.0 rom sizes are either a bank count or, with an msb nibble of $F,
+    // an exponent-multiplier pair packed in the lsb byte
+    fn rom_size(lsb: u8, msb: u8, bank_size: usize) -> usize {
+        if msb == 0x0F {
+            let exponent = (lsb >> 2) as u32;
+            let multiplier = ((lsb & 0x03) * 2 + 1) as usize;
+            return (1usize << exponent.min(48)) * multiplier;
+        }
+        return (((msb as usize) << 8) | lsb as usize) * bank_size;
+    }
+
+    fn ram_size(shift: u8) -> usize {
+        if shift == 0 {
+            return 0;
+        }
+        return 64 << shift;
+    }
+
+    /// Number of 16K PRG banks as seen by the mappers
+    pub fn prg_banks(&self) -> usize {
+        return self.prg_rom_size / (16 * 1024);
+    }
+
+    /// Number of 8K CHR ROM banks as seen by the mappers, 0 means CHR RAM
+    pub fn chr_banks(&self) -> usize {
+        return self.chr_rom_size / (8 * 1024);
+    }
+
+    pub fn board_name(&self) -> &'static str {
+        return mapper_name(self.mapper_id).unwrap_or("Unknown");
+    }
+}
+
+fn format_size(size: usize) -> String {
+    if size >= 1024 {
+        return format!("{}K", size / 1024);
+    }
+    return format!("{}B", size);
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Mapper {}.{} ({})",
+            self.mapper_id,
+            self.submapper_id,
+            self.board_name()
+        )?;
+        write!(f, " | PRG {}", format_size(self.prg_rom_size))?;
+        if self.chr_rom_size > 0 {
+            write!(f, " | CHR {}", format_size(self.chr_rom_size))?;
+        } else {
+            write!(f, " | CHR RAM {}", format_size(self.chr_ram_size + self.chr_nvram_size))?;
+        }
+        if self.prg_ram_size + self.prg_nvram_size > 0 {
+            write!(
+                f,
+                " | PRG RAM {}",
+                format_size(self.prg_ram_size + self.prg_nvram_size)
+            )?;
+        }
+        if self.battery {
+            write!(f, " | Battery")?;
+        }
+        let region = match self.timing {
+            TimingMode::NTSC => "NTSC",
+            TimingMode::PAL => "PAL",
+            TimingMode::MULTIPLE => "Multi-region",
+            TimingMode::DENDY => "Dendy",
+        };
+        write!(f, " | {}", region)?;
+        if self.format == HeaderFormat::NES20 {
+            write!(f, " | NES 2.0")?;
+        }
+        return Ok(());
+    }
+}
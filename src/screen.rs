@@ -2,29 +2,37 @@ use crate::audio::Audio;
 use crate::audio::AUDIO_THREAD_ACTIVE;
 use crate::cpu::Cpu;
 use crate::gui::Message;
+use crate::nes::Frame;
 use crate::nes::NES_PTR;
-use crate::nes::SPRITE_ARR_SIZE;
-use iced::canvas::{self, Cache, Canvas, Cursor, Frame, Geometry};
+use crate::nes::FRAME_SIZE;
+use crate::region::Region;
+use iced::image;
 use iced::Element;
+use iced::Image;
 use iced::Length;
-use iced::Rectangle;
-use iced_native::{Color, Point, Size};
+use iced::Space;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use tokio::task::JoinHandle;
 
 extern crate redis;
 
 #[derive(Default)]
 pub struct ScreenState {
-    cache: Cache,
-    pub scale: f32,
+    // Whole multiple of the NES resolution, optionally stretched to the pixel aspect
+    // of the region the Nes runs at
+    pub scale: u16,
+    pub aspect: bool,
+    pub region: Region,
 }
 
 pub struct Screen {
     state: ScreenState,
     client: bool,
-    pal_screen: [Color; 64],
     audio_thread: Option<JoinHandle<()>>,
+    // Latest frame, kept to redraw it when the size changes
+    frame: Option<Arc<Frame>>,
+    image: Option<image::Handle>,
 }
 
 impl Default for Screen {
@@ -38,76 +46,13 @@ impl Screen {
         Screen {
             client,
             state: ScreenState {
-                scale: 2.0,
-                ..ScreenState::default()
+                scale: 2,
+                aspect: false,
+                region: Region::NTSC,
             },
-            pal_screen: [
-                Color::from_rgb8(84, 84, 84),
-                Color::from_rgb8(0, 30, 116),
-                Color::from_rgb8(8, 16, 144),
-                Color::from_rgb8(48, 0, 136),
-                Color::from_rgb8(68, 0, 100),
-                Color::from_rgb8(92, 0, 48),
-                Color::from_rgb8(84, 4, 0),
-                Color::from_rgb8(60, 24, 0),
-                Color::from_rgb8(32, 42, 0),
-                Color::from_rgb8(8, 58, 0),
-                Color::from_rgb8(0, 64, 0),
-                Color::from_rgb8(0, 60, 0),
-                Color::from_rgb8(0, 50, 60),
-                Color::from_rgb8(0, 0, 0),
-                Color::from_rgb8(0, 0, 0),
-                Color::from_rgb8(0, 0, 0),
-                Color::from_rgb8(152, 150, 152),
-                Color::from_rgb8(8, 76, 196),
-                Color::from_rgb8(48, 50, 236),
-                Color::from_rgb8(92, 30, 228),
-                Color::from_rgb8(136, 20, 176),
-                Color::from_rgb8(160, 20, 100),
-                Color::from_rgb8(152, 34, 32),
-                Color::from_rgb8(120, 60, 0),
-                Color::from_rgb8(84, 90, 0),
-                Color::from_rgb8(40, 114, 0),
-                Color::from_rgb8(8, 124, 0),
-                Color::from_rgb8(0, 118, 40),
-                Color::from_rgb8(0, 102, 120),
-                Color::from_rgb8(0, 0, 0),
-                Color::from_rgb8(0, 0, 0),
-                Color::from_rgb8(0, 0, 0),
-                Color::from_rgb8(236, 238, 236),
-                Color::from_rgb8(76, 154, 236),
-                Color::from_rgb8(120, 124, 236),
-                Color::from_rgb8(176, 98, 236),
-                Color::from_rgb8(228, 84, 236),
-                Color::from_rgb8(236, 88, 180),
-                Color::from_rgb8(236, 106, 100),
-                Color::from_rgb8(212, 136, 32),
-                Color::from_rgb8(160, 170, 0),
-                Color::from_rgb8(116, 196, 0),
-                Color::from_rgb8(76, 208, 32),
-                Color::from_rgb8(56, 204, 108),
-                Color::from_rgb8(56, 180, 204),
-                Color::from_rgb8(60, 60, 60),
-                Color::from_rgb8(0, 0, 0),
-                Color::from_rgb8(0, 0, 0),
-                Color::from_rgb8(236, 238, 236),
-                Color::from_rgb8(168, 204, 236),
-                Color::from_rgb8(188, 188, 236),
-                Color::from_rgb8(212, 178, 236),
-                Color::from_rgb8(236, 174, 236),
-                Color::from_rgb8(236, 174, 212),
-                Color::from_rgb8(236, 180, 176),
-                Color::from_rgb8(228, 196, 144),
-                Color::from_rgb8(204, 210, 120),
-                Color::from_rgb8(180, 222, 120),
-                Color::from_rgb8(168, 226, 144),
-                Color::from_rgb8(152, 226, 180),
-                Color::from_rgb8(160, 214, 228),
-                Color::from_rgb8(160, 162, 160),
-                Color::from_rgb8(0, 0, 0),
-                Color::from_rgb8(0, 0, 0),
-            ],
             audio_thread: None,
+            frame: None,
+            image: None,
         }
     }
 
@@ -116,8 +61,11 @@ impl Screen {
             return ();
         }
         let mut nes = NES_PTR.lock().unwrap();
+        let cart = match &nes.cart {
+            Some(cart) => cart.clone(),
+            None => return (),
+        };
         nes.cpu = Cpu::new();
-        let cart = nes.cart.as_ref().unwrap().clone();
         // nes.cart = Some(cart.clone());
         nes.cpu.bus.insert_cartridge(cart.clone());
         nes.cpu.reset();
@@ -144,38 +92,87 @@ impl Screen {
     }
 
     pub fn view(&mut self) -> Element<Message> {
-        let scale = self.state.scale;
-        Canvas::new(self)
-            .width(Length::Units((256.0 * scale) as u16))
-            .height(Length::Units((240.0 * scale) as u16))
-            .into()
+        let (width, height) = self.size();
+        match &self.image {
+            Some(handle) => Image::new(handle.clone())
+                .width(Length::Units(width))
+                .height(Length::Units(height))
+                .into(),
+            None => Space::new(Length::Units(width), Length::Units(height)).into(),
+        }
     }
-    pub fn request_redraw(&mut self) {
-        self.state.cache.clear()
+
+    // Size on screen in pixels
+    fn size(&self) -> (u16, u16) {
+        let scale = self.state.scale as f32;
+        let mut width = 256.0 * scale;
+        if self.state.aspect {
+            width = width * self.state.region.pixel_aspect();
+        }
+        return (width.round() as u16, (240.0 * scale) as u16);
+    }
+
+    pub fn scale(&self) -> u16 {
+        return self.state.scale;
+    }
+
+    pub fn set_scale(&mut self, scale: u16) {
+        self.state.scale = scale.max(1);
+        if let Some(frame) = self.frame.take() {
+            self.show_frame(frame);
+        }
+    }
+
+    pub fn aspect(&self) -> bool {
+        return self.state.aspect;
+    }
+
+    pub fn set_aspect(&mut self, aspect: bool) {
+        self.state.aspect = aspect;
+        if let Some(frame) = self.frame.take() {
+            self.show_frame(frame);
+        }
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.state.region = region;
+        if let Some(frame) = self.frame.take() {
+            self.show_frame(frame);
+        }
     }
-}
 
-impl canvas::Program<Message> for Screen {
-    fn draw(&self, bounds: Rectangle, _cursor: Cursor) -> Vec<Geometry> {
-        let nes = NES_PTR.lock().unwrap();
-        if nes.pal_positions.len() < SPRITE_ARR_SIZE {
-            // println!("No {}", nes.pal_positions.len());
-            return vec![];
+    // Show a 256x240 RGBA frame. iced 0.3 always samples images linearly, so the
+    // frame is scaled nearest neighbour to the exact size it is drawn at, aspect
+    // stretch included, and the texture is drawn 1:1. iced wants BGRA
+    pub fn show_frame(&mut self, frame: Arc<Frame>) {
+        if frame.rgba.len() < FRAME_SIZE {
+            return;
         }
-        let content = self.state.cache.draw(bounds.size(), |frame: &mut Frame| {
-            for i in 0..256 {
-                for j in 0..240 {
-                    frame.fill_rectangle(
-                        Point::new(
-                            i as f32 * self.state.scale as f32,
-                            j as f32 * self.state.scale as f32,
-                        ),
-                        Size::new(1.0 * self.state.scale, 1.0 * self.state.scale),
-                        self.pal_screen[nes.pal_positions[(j * 256) + i] as usize],
-                    );
-                }
+        let (width, height) = self.size();
+        let (width, height) = (width as usize, height as usize);
+        let stride = width * 4;
+
+        // Source column of every screen column
+        let columns: Vec<usize> = (0..width).map(|x| x * 256 / width).collect();
+        let mut pixels = vec![0; stride * height];
+        let mut last_row = usize::MAX;
+        for y in 0..height {
+            let row = y * 240 / height;
+            let start = y * stride;
+            if row == last_row {
+                // Repeated lines are a straight copy of the one above
+                pixels.copy_within(start - stride..start, start);
+                continue;
             }
-        });
-        vec![content]
+            let line = &frame.rgba[row * 256 * 4..(row + 1) * 256 * 4];
+            for (x, column) in columns.iter().enumerate() {
+                let rgba = &line[column * 4..column * 4 + 4];
+                pixels[start + x * 4..start + x * 4 + 4]
+                    .copy_from_slice(&[rgba[2], rgba[1], rgba[0], rgba[3]]);
+            }
+            last_row = row;
+        }
+        self.frame = Some(frame);
+        self.image = Some(image::Handle::from_pixels(width as u32, height as u32, pixels));
     }
 }
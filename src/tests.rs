@@ -0,0 +1,758 @@
+// Accuracy tests driven by test ROMs. The ROMs are not distributed with the
+// crate, drop them under test_roms/ (see README) and run the ignored tests with
+// `cargo test -- --ignored`, a missing ROM fails. The rest run on small
+// programs assembled into an NROM image here.
+
+use crate::cartridge::{Cartridge, RomError};
+use crate::debugger::{BreakReason, WatchKind};
+use crate::nes::{Nes, FRAME_SIZE, SPRITE_ARR_SIZE};
+use crate::region::Region;
+use crate::rom_info::{HeaderFormat, RomInfo, TimingMode};
+use crate::tracer::Tracer;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+// blargg status byte values at $6000
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET: u8 = 0x81;
+
+// NTSC frames before a test ROM is considered hung
+const FRAME_LIMIT: u32 = 60 * 60;
+
+fn rom_path(name: &str) -> PathBuf {
+    return PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("test_roms")
+        .join(name);
+}
+
+// Boot a ROM from test_roms/ headlessly
+fn boot(name: &str) -> Nes {
+    let path = rom_path(name);
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(err) => panic!("Could not read {}: {}, see README", path.display(), err),
+    };
+    let cart = match Cartridge::from_bytes(&data) {
+        Ok(cart) => cart,
+        Err(err) => panic!("Could not load {}: {}", path.display(), err),
+    };
+    return boot_cart(cart);
+}
+
+// Boot a 32K NROM image with program at $8000. NMI and IRQ go to an RTI at $FFF0
+fn boot_program(program: &[u8]) -> Nes {
+    let mut rom = vec![0; 16 + 0x8000 + 0x2000];
+    rom[0..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, 2, 1, 0, 0]);
+    let prg = &mut rom[16..16 + 0x8000];
+    prg[..program.len()].copy_from_slice(program);
+    prg[0x7FF0] = 0x40;
+    prg[0x7FFA..].copy_from_slice(&[0xF0, 0xFF, 0x00, 0x80, 0xF0, 0xFF]);
+    let cart = match Cartridge::from_bytes(&rom) {
+        Ok(cart) => cart,
+        Err(err) => panic!("Could not load test program: {}", err),
+    };
+    return boot_cart(cart);
+}
+
+// iNES image for mapper tests. Every 16K PRG bank and 4K CHR bank is filled with
+// its own number, the program runs from $C000 at the start of the last PRG bank
+fn banked_rom(mapper: u8, prg_banks: usize, chr_banks: usize, program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 16];
+    rom[0..8].copy_from_slice(&[b'N', b'E', b'S', 0x1A, prg_banks as u8, chr_banks as u8, 0, 0]);
+    rom[6] = (mapper & 0x0F) << 4;
+    rom[7] = mapper & 0xF0;
+    for bank in 0..prg_banks {
+        rom.extend_from_slice(&[bank as u8; 0x4000]);
+    }
+    let last = 16 + (prg_banks - 1) * 0x4000;
+    rom[last..last + program.len()].copy_from_slice(program);
+    rom[last + 0x3FF0] = 0x40;
+    rom[last + 0x3FFA..last + 0x4000].copy_from_slice(&[0xF0, 0xFF, 0x00, 0xC0, 0xF0, 0xFF]);
+    for bank in 0..chr_banks * 2 {
+        rom.extend_from_slice(&[bank as u8; 0x1000]);
+    }
+    return rom;
+}
+
+fn boot_banked(mapper: u8, prg_banks: usize, chr_banks: usize, program: &[u8]) -> Nes {
+    let cart = match Cartridge::from_bytes(&banked_rom(mapper, prg_banks, chr_banks, program)) {
+        Ok(cart) => cart,
+        Err(err) => panic!("Could not load test program: {}", err),
+    };
+    return boot_cart(cart);
+}
+
+// CHR byte the cartridge maps at a ppu address
+fn chr_peek(nes: &Nes, addr: u16) -> u8 {
+    let mut data = 0;
+    nes.cart.as_ref().unwrap().lock().unwrap().ppu_read(addr, &mut data);
+    return data;
+}
+
+fn boot_cart(cart: Cartridge) -> Nes {
+    let mut nes = Nes::new();
+    nes.insert_cartridge(cart);
+    let cart = nes.cart.clone().unwrap();
+    nes.cpu.bus.insert_cartridge(cart);
+    let region = nes.cart_region();
+    nes.set_region(region);
+    nes.cpu.reset();
+    return nes;
+}
+
+fn run_frame(nes: &mut Nes) {
+    while !nes.cpu.bus.get_ppu().frame_complete {
+        nes.clock();
+    }
+    nes.cpu.bus.get_ppu().frame_complete = false;
+}
+
+// Trace sink the test can read back
+#[derive(Clone)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+#[test]
+#[ignore = "needs test_roms/nestest"]
+fn nestest() {
+    let mut nes = boot("nestest/nestest.nes");
+    let expected = match fs::read_to_string(rom_path("nestest/nestest.log")) {
+        Ok(log) => log,
+        Err(err) => panic!("Could not read nestest.log: {}", err),
+    };
+    let expected: Vec<&str> = expected.lines().collect();
+
+    // Automation mode starts at $C000 and needs no PPU
+    let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+    nes.cpu.pc = 0xC000;
+    nes.set_tracer(Some(Tracer::new(Box::new(buffer.clone()))));
+
+    // Our PPU starts the frame at a different dot, so only registers and
+    // CPU cycles are compared
+    let registers = |line: &str| line.get(..73).unwrap_or(line).to_owned();
+    let cycles = |line: &str| line.rsplit("CYC:").next().unwrap_or("").trim().to_owned();
+
+    let last_cycle: u64 = cycles(expected[expected.len() - 1]).parse().unwrap();
+    while nes.cpu.clock_count <= last_cycle {
+        nes.clock();
+    }
+    nes.set_tracer(None);
+
+    let trace = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let trace: Vec<&str> = trace.lines().collect();
+    assert!(trace.len() >= expected.len(), "nestest stopped after {} lines", trace.len());
+    for (i, (actual, expected)) in trace.iter().zip(expected.iter()).enumerate() {
+        assert!(
+            registers(actual) == registers(expected) && cycles(actual) == cycles(expected),
+            "nestest diverged at line {}\nexpected: {}\nactual:   {}",
+            i + 1,
+            expected,
+            actual
+        );
+    }
+
+    // Error codes for the official and unofficial opcode tests
+    assert_eq!(nes.cpu.read(0x0002, true), 0x00, "official opcode error");
+    assert_eq!(nes.cpu.read(0x0003, true), 0x00, "unofficial opcode error");
+}
+
+#[test]
+fn frame_events() {
+    // JMP $8000
+    let mut nes = boot_program(&[0x4C, 0x00, 0x80]);
+    let frames = nes.subscribe_frames();
+    run_frame(&mut nes);
+    let first = frames.try_recv().expect("no frame after the first vblank");
+    // Delivered as vblank starts, not when the pre-render line comes around
+    assert_eq!(nes.cpu.bus.get_ppu().get_scanline(), Region::NTSC.vblank_line());
+    assert_eq!(nes.cpu.bus.get_ppu().get_cycle(), 2);
+    run_frame(&mut nes);
+
+    // Exactly one complete frame per vblank
+    let second = frames.try_recv().expect("no frame after the second vblank");
+    assert!(frames.try_recv().is_err());
+    assert_eq!(second.number, first.number + 1);
+    assert_eq!(second.rgba.len(), FRAME_SIZE);
+    assert_eq!(second.positions.len(), SPRITE_ARR_SIZE);
+    assert_eq!(second.rgba, nes.cpu.bus.get_ppu().frame());
+
+    // A listener that falls behind misses frames rather than queueing all of them
+    for _ in 0..5 {
+        run_frame(&mut nes);
+    }
+    let mut queued = 0;
+    while frames.try_recv().is_ok() {
+        queued = queued + 1;
+    }
+    assert!(queued > 0 && queued < 5, "{} frames were queued", queued);
+}
+
+// Clock until the debugger stops the cpu
+fn run_until_paused(nes: &mut Nes) {
+    for _ in 0..100_000 {
+        nes.clock();
+        if nes.cpu.bus.debugger.is_paused() {
+            return;
+        }
+    }
+    panic!("Debugger never paused");
+}
+
+#[test]
+fn debugger_resume_after_watchpoint() {
+    // LDA $10, LDA $11, JMP $8000
+    let mut nes = boot_program(&[0xA5, 0x10, 0xA5, 0x11, 0x4C, 0x00, 0x80]);
+    nes.cpu.bus.debugger.add_watchpoint(0x0010, 0x0010, WatchKind::READ);
+    run_until_paused(&mut nes);
+
+    // The read stops the cpu inside LDA $10, one step finishes it
+    nes.cpu.bus.debugger.step_instruction();
+    run_until_paused(&mut nes);
+    assert_eq!(nes.cpu.bus.debugger.break_reason(), Some(BreakReason::STEP));
+    assert_eq!(nes.cpu.pc, 0x8002);
+
+    // Stepping from an instruction boundary runs exactly one instruction
+    nes.cpu.bus.debugger.step_instruction();
+    run_until_paused(&mut nes);
+    assert_eq!(nes.cpu.pc, 0x8004);
+
+    // A breakpoint right after a watchpoint stop is not skipped
+    nes.cpu.bus.debugger.add_breakpoint(0x8002);
+    nes.cpu.bus.debugger.resume();
+    run_until_paused(&mut nes);
+    assert!(matches!(nes.cpu.bus.debugger.break_reason(), Some(BreakReason::WATCHPOINT(..))));
+    nes.cpu.bus.debugger.resume();
+    run_until_paused(&mut nes);
+    assert_eq!(nes.cpu.bus.debugger.break_reason(), Some(BreakReason::BREAKPOINT(0x8002)));
+    assert_eq!(nes.cpu.pc, 0x8002);
+}
+
+#[test]
+fn apu_frame_irq() {
+    // JMP $8000, the I flag from reset keeps the IRQ from being taken
+    let mut nes = boot_program(&[0x4C, 0x00, 0x80]);
+    run_frame(&mut nes);
+    run_frame(&mut nes);
+    assert!(nes.cpu.bus.apu.irq(), "4-step mode raises the frame IRQ");
+    assert_eq!(nes.cpu.bus.peek(0x4015) & 0x40, 0x40);
+    assert!(nes.cpu.bus.irq_line());
+
+    // Reading $4015 acknowledges it
+    nes.cpu.bus.read(0x4015, false);
+    assert!(!nes.cpu.bus.apu.irq());
+    assert_eq!(nes.cpu.bus.peek(0x4015) & 0x40, 0x00);
+
+    // Setting the inhibit bit clears a raised flag and keeps it down
+    run_frame(&mut nes);
+    run_frame(&mut nes);
+    assert!(nes.cpu.bus.apu.irq());
+    nes.cpu.bus.write(0x4017, 0x40);
+    assert!(!nes.cpu.bus.apu.irq());
+    run_frame(&mut nes);
+    run_frame(&mut nes);
+    assert!(!nes.cpu.bus.apu.irq(), "inhibited frame IRQ was raised");
+
+    // 5-step mode never raises it
+    nes.cpu.bus.write(0x4017, 0x80);
+    run_frame(&mut nes);
+    run_frame(&mut nes);
+    assert!(!nes.cpu.bus.apu.irq(), "5-step mode raised the frame IRQ");
+
+    nes.cpu.bus.write(0x4017, 0x00);
+    run_frame(&mut nes);
+    run_frame(&mut nes);
+    assert!(nes.cpu.bus.apu.irq());
+}
+
+#[test]
+fn apu_frame_irq_period() {
+    // CPU cycles between two 4-step frame IRQs
+    let period = |region: Region| {
+        // JMP $8000
+        let mut nes = boot_program(&[0x4C, 0x00, 0x80]);
+        nes.set_region(region);
+        let mut raised = vec![];
+        while raised.len() < 2 {
+            nes.clock();
+            if nes.cpu.bus.apu.irq() {
+                raised.push(nes.cpu.clock_count);
+                nes.cpu.bus.read(0x4015, false);
+            }
+        }
+        return raised[1] - raised[0];
+    };
+    // 29830 cpu cycles on NTSC and 33254 on PAL, both tables round the same way
+    assert_eq!(period(Region::PAL) - period(Region::NTSC), 33254 - 29830);
+}
+
+// Clock to an instruction boundary once the frame IRQ is up
+fn run_until_frame_irq(nes: &mut Nes) {
+    for _ in 0..1_000_000 {
+        nes.clock();
+        if nes.cpu.bus.apu.irq() && nes.cpu.step == 0 {
+            return;
+        }
+    }
+    panic!("Frame IRQ never came up");
+}
+
+// Addresses of the instructions run over the next cpu cycles
+fn trace_pcs(nes: &mut Nes, cycles: u64) -> Vec<String> {
+    let buffer = SharedBuffer(Arc::new(Mutex::new(Vec::new())));
+    nes.set_tracer(Some(Tracer::new(Box::new(buffer.clone()))));
+    let end = nes.cpu.clock_count + cycles;
+    while nes.cpu.clock_count < end {
+        nes.clock();
+    }
+    nes.set_tracer(None);
+    let trace = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    return trace.lines().map(|line| line[..4].to_owned()).collect();
+}
+
+#[test]
+fn cli_sei_latency() {
+    // JMP $8000 until the frame IRQ is up, then CLI, NOP, NOP at $8010 or SEI, NOP, NOP at $8020
+    let mut program = vec![0; 0x30];
+    program[0x00..0x03].copy_from_slice(&[0x4C, 0x00, 0x80]);
+    program[0x10..0x16].copy_from_slice(&[0x58, 0xEA, 0xEA, 0x4C, 0x12, 0x80]);
+    program[0x20..0x26].copy_from_slice(&[0x78, 0xEA, 0xEA, 0x4C, 0x22, 0x80]);
+
+    // CLI clears I after the poll, one more instruction runs before the IRQ
+    let mut nes = boot_program(&program);
+    run_until_frame_irq(&mut nes);
+    nes.cpu.pc = 0x8010;
+    let pcs = trace_pcs(&mut nes, 20);
+    assert_eq!(pcs[..3], ["8010", "8011", "FFF0"]);
+
+    // SEI sets I after the poll, the IRQ is still taken right after it and
+    // returns with I set
+    let mut nes = boot_program(&program);
+    run_until_frame_irq(&mut nes);
+    nes.cpu.status = nes.cpu.status & !0x04;
+    nes.cpu.pc = 0x8020;
+    let pcs = trace_pcs(&mut nes, 20);
+    assert_eq!(pcs[..3], ["8020", "FFF0", "8021"]);
+}
+
+// Load an MMC1 register through the serial port, low bit first
+fn mmc1_write(nes: &mut Nes, addr: usize, value: u8) {
+    for bit in 0..5 {
+        nes.cpu.bus.write(addr, (value >> bit) & 0x01);
+    }
+}
+
+#[test]
+fn mmc1_banks_follow_mode() {
+    // JMP $C000
+    let mut nes = boot_banked(1, 8, 2, &[0x4C, 0x00, 0xC0]);
+
+    // Mode 3 fixes the last bank at $C000
+    mmc1_write(&mut nes, 0xE000, 2);
+    assert_eq!(nes.cpu.read(0x8000, true), 2);
+    assert_eq!(nes.cpu.read(0xE000, true), 7);
+
+    // Switching modes later remaps the bank selected before
+    mmc1_write(&mut nes, 0x8000, 0x08);
+    assert_eq!(nes.cpu.read(0x8000, true), 0);
+    assert_eq!(nes.cpu.read(0xE000, true), 2);
+    mmc1_write(&mut nes, 0x8000, 0x00);
+    assert_eq!(nes.cpu.read(0x8000, true), 2);
+    assert_eq!(nes.cpu.read(0xE000, true), 3);
+
+    // Both CHR registers are kept whatever the CHR mode
+    mmc1_write(&mut nes, 0x8000, 0x10);
+    mmc1_write(&mut nes, 0xA000, 3);
+    mmc1_write(&mut nes, 0xC000, 1);
+    assert_eq!(chr_peek(&nes, 0x0000), 3);
+    assert_eq!(chr_peek(&nes, 0x1000), 1);
+    mmc1_write(&mut nes, 0x8000, 0x00);
+    assert_eq!(chr_peek(&nes, 0x0000), 2);
+    assert_eq!(chr_peek(&nes, 0x1000), 3);
+    mmc1_write(&mut nes, 0x8000, 0x10);
+    assert_eq!(chr_peek(&nes, 0x1000), 1);
+}
+
+#[test]
+fn mmc1_ignores_consecutive_writes() {
+    // INC $E000 writes $0F then $10 on back to back cycles, only the first
+    // shifts in. Then 1, 0, 0, 0 finish the PRG bank as 3
+    let program = [
+        0xEE, 0x00, 0xE0, // INC $E000
+        0xA9, 0x01, // LDA #$01
+        0x8D, 0x00, 0xE0, // STA $E000
+        0xA9, 0x00, // LDA #$00
+        0x8D, 0x00, 0xE0, // STA $E000
+        0x8D, 0x00, 0xE0, // STA $E000
+        0x8D, 0x00, 0xE0, // STA $E000
+        0x4C, 0x13, 0xC0, // JMP $C013
+    ];
+    let mut nes = boot_banked(1, 16, 0, &program);
+    run_frame(&mut nes);
+    assert_eq!(nes.cpu.read(0x8000, true), 3);
+}
+
+#[test]
+fn mmc3_banks_chr_ram() {
+    // JMP $C000 on a board with 8K of CHR RAM
+    let mut nes = boot_banked(4, 4, 0, &[0x4C, 0x00, 0xC0]);
+    let cart = nes.cart.clone().unwrap();
+
+    // R0 = 2 puts 1K banks 2 and 3 at $0000
+    nes.cpu.bus.write(0x8000, 0x00);
+    nes.cpu.bus.write(0x8001, 0x02);
+    cart.lock().unwrap().ppu_write(0x0000, 0xAB);
+
+    // R1 maps bank 2 at $0800 after reset
+    nes.cpu.bus.write(0x8001, 0x00);
+    assert_eq!(chr_peek(&nes, 0x0000), 0x00);
+    assert_eq!(chr_peek(&nes, 0x0800), 0xAB);
+}
+
+// Header with bytes 4-15 as given
+fn header(bytes: [u8; 12]) -> [u8; 16] {
+    let mut header = [0; 16];
+    header[0..4].copy_from_slice(b"NES\x1A");
+    header[4..16].copy_from_slice(&bytes);
+    return header;
+}
+
+#[test]
+fn rom_info_mapper_ids() {
+    // (case, bytes 4-15, NES 2.0, mapper, submapper)
+    let cases = [
+        ("iNES low nibble", [1, 1, 0x40, 0x00, 0, 0, 0, 0, 0, 0, 0, 0], false, 4, 0),
+        ("iNES high nibble", [1, 1, 0x10, 0x40, 0, 0, 0, 0, 0, 0, 0, 0], false, 65, 0),
+        ("NES 2.0 plane", [1, 1, 0x50, 0x48, 0x21, 0, 0, 0, 0, 0, 0, 0], true, 325, 2),
+        ("NES 2.0 submapper", [1, 1, 0x40, 0x08, 0x10, 0, 0, 0, 0, 0, 0, 0], true, 4, 1),
+    ];
+    for (case, bytes, nes20, mapper, submapper) in cases.iter() {
+        let info = RomInfo::parse(&header(*bytes));
+        assert!((info.format == HeaderFormat::NES20) == *nes20, "{} format", case);
+        assert_eq!(info.mapper_id, *mapper, "{} mapper", case);
+        assert_eq!(info.submapper_id, *submapper, "{} submapper", case);
+    }
+}
+
+#[test]
+fn rom_info_sizes() {
+    // (case, bytes 4-15, PRG ROM, CHR ROM, PRG RAM, PRG NVRAM, CHR RAM, CHR NVRAM)
+    let cases = [
+        ("iNES", [2, 1, 0x00, 0x00, 0, 0, 0, 0, 0, 0, 0, 0], 0x8000, 0x2000, 0x2000, 0, 0, 0),
+        ("iNES battery", [2, 0, 0x02, 0x00, 4, 0, 0, 0, 0, 0, 0, 0], 0x8000, 0, 0, 0x8000, 0x2000, 0),
+        ("NES 2.0 msb", [2, 1, 0x00, 0x08, 0, 0x21, 0, 0, 0, 0, 0, 0], 0x102 * 0x4000, 0x201 * 0x2000, 0, 0, 0, 0),
+        // 2^10 * 3 and 2^13 * 1
+        ("NES 2.0 exponent", [0x29, 0x34, 0x00, 0x08, 0, 0xFF, 0, 0, 0, 0, 0, 0], 3072, 0x2000, 0, 0, 0, 0),
+        // 64 << 7, 64 << 9, 64 << 7
+        ("NES 2.0 RAM shifts", [1, 0, 0x02, 0x08, 0, 0, 0x97, 0x07, 0, 0, 0, 0], 0x4000, 0, 0x2000, 0x8000, 0x2000, 0),
+    ];
+    for (case, bytes, prg, chr, prg_ram, prg_nvram, chr_ram, chr_nvram) in cases.iter() {
+        let info = RomInfo::parse(&header(*bytes));
+        assert_eq!(info.prg_rom_size, *prg, "{} PRG ROM", case);
+        assert_eq!(info.chr_rom_size, *chr, "{} CHR ROM", case);
+        assert_eq!(info.prg_ram_size, *prg_ram, "{} PRG RAM", case);
+        assert_eq!(info.prg_nvram_size, *prg_nvram, "{} PRG NVRAM", case);
+        assert_eq!(info.chr_ram_size, *chr_ram, "{} CHR RAM", case);
+        assert_eq!(info.chr_nvram_size, *chr_nvram, "{} CHR NVRAM", case);
+    }
+}
+
+#[test]
+fn rom_info_dirty_header() {
+    // Clean iNES headers use the high mapper nibble and the PAL bit
+    let info = RomInfo::parse(&header([1, 1, 0x10, 0x40, 0, 0x01, 0, 0, 0, 0, 0, 0]));
+    assert_eq!(info.mapper_id, 65);
+    assert!(info.timing == TimingMode::PAL);
+
+    // Anything in bytes 12-15 marks a dirty header, e.g. "DiskDude!" from byte 7
+    let mut dirty = [0; 12];
+    dirty[0..3].copy_from_slice(&[1, 1, 0x10]);
+    dirty[3..12].copy_from_slice(b"DiskDude!");
+    let info = RomInfo::parse(&header(dirty));
+    assert_eq!(info.mapper_id, 1);
+    assert!(info.timing == TimingMode::NTSC);
+
+    // A single stray byte is enough
+    let info = RomInfo::parse(&header([1, 1, 0x10, 0x40, 0, 0x01, 0, 0, 0, 0, 0, 0x01]));
+    assert_eq!(info.mapper_id, 1);
+    assert!(info.timing == TimingMode::NTSC);
+}
+
+// Header followed by data bytes of filler
+fn rom_bytes(bytes: [u8; 12], data: usize) -> Vec<u8> {
+    let mut rom = header(bytes).to_vec();
+    rom.resize(16 + data, 0);
+    return rom;
+}
+
+#[test]
+fn rom_errors() {
+    let mut bad_magic = rom_bytes([1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 0x6000);
+    bad_magic[3] = 0x1B;
+    let cases: [(&str, Vec<u8>, fn(&RomError) -> bool); 7] = [
+        ("bad magic", bad_magic, |err| matches!(err, RomError::BadMagic)),
+        ("short header", b"NES\x1A".to_vec(), |err| matches!(err, RomError::TruncatedHeader)),
+        ("trainer", rom_bytes([1, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0], 0x100), |err| {
+            matches!(err, RomError::TruncatedTrainer)
+        }),
+        ("no PRG", rom_bytes([0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 0x2000), |err| {
+            matches!(err, RomError::NoPrgRom)
+        }),
+        ("short PRG", rom_bytes([2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 0x4000), |err| {
+            matches!(err, RomError::TruncatedPrg { expected: 0x8000, found: 0x4000 })
+        }),
+        ("short CHR", rom_bytes([1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 0x5000), |err| {
+            matches!(err, RomError::TruncatedChr { expected: 0x2000, found: 0x1000 })
+        }),
+        // MMC5
+        ("mapper", rom_bytes([1, 1, 0x50, 0, 0, 0, 0, 0, 0, 0, 0, 0], 0x6000), |err| {
+            matches!(err, RomError::UnsupportedMapper(5))
+        }),
+    ];
+    for (case, rom, expected) in cases.iter() {
+        match Cartridge::from_bytes(rom) {
+            Ok(_) => panic!("{} loaded", case),
+            Err(err) => assert!(expected(&err), "{} gave {:?}", case, err),
+        }
+    }
+
+    // The same images load once they are whole
+    let rom = rom_bytes([1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], 0x6000);
+    assert!(Cartridge::from_bytes(&rom).is_ok());
+    let rom = rom_bytes([1, 0, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0], 0x200 + 0x4000);
+    assert!(Cartridge::from_bytes(&rom).is_ok());
+}
+
+#[test]
+fn runs_without_cartridge() {
+    // Nothing answers the cartridge space, the cpu reads open bus and keeps going
+    let mut nes = Nes::new();
+    nes.cpu.reset();
+    run_frame(&mut nes);
+    run_frame(&mut nes);
+    assert_eq!(nes.cpu.read(0x8000, true), nes.cpu.read(0x9000, true));
+}
+
+#[test]
+fn battery_ram_skips_volatile_ram() {
+    // NES 2.0 NROM with 8K of PRG RAM followed by 8K of battery backed PRG NVRAM
+    let rom = rom_bytes([1, 1, 0x02, 0x08, 0, 0, 0x77, 0, 0, 0, 0, 0], 0x6000);
+    let dir = std::env::temp_dir().join(format!("rust_nes_battery_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let rom_path = dir.join("battery.nes");
+    let save_path = dir.join("battery.sav");
+    fs::write(&rom_path, &rom).unwrap();
+
+    let mut cart = Cartridge::load(rom_path.to_str().unwrap()).unwrap();
+    assert_eq!(cart.v_prg_ram.len(), 0x4000);
+    cart.cpu_write(0x6000, 0x11);
+    cart.v_prg_ram[0x2000] = 0x22;
+    cart.save_battery_ram().unwrap();
+    let save = fs::read(&save_path).unwrap();
+    assert_eq!(save.len(), 0x2000);
+    assert_eq!(save[0], 0x22);
+
+    let cart = Cartridge::load(rom_path.to_str().unwrap()).unwrap();
+    assert_eq!(cart.v_prg_ram[0x0000], 0x00);
+    assert_eq!(cart.v_prg_ram[0x2000], 0x22);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn region_timing() {
+    for region in [Region::NTSC, Region::PAL, Region::DENDY] {
+        let mut nes = boot_program(&[0x4C, 0x00, 0x80]);
+        nes.set_region(region);
+        run_frame(&mut nes);
+        let start = nes.cpu.clock_count;
+        run_frame(&mut nes);
+
+        // A whole frame of dots in cpu cycles, NTSC drops one dot per frame
+        let mut dots = 341 * region.scanlines() as u64;
+        if region.skips_dot() {
+            dots = dots - 1;
+        }
+        let (cpu_cycles, ppu_dots) = region.cpu_ppu_ratio();
+        let expected = dots * cpu_cycles as u64 / ppu_dots as u64;
+        let actual = nes.cpu.clock_count - start;
+        assert!(
+            actual == expected || actual == expected + 1,
+            "{} frame took {} cpu cycles, expected {}",
+            region.name(),
+            actual,
+            expected
+        );
+    }
+}
+
+#[test]
+fn region_frame_layout() {
+    for region in [Region::NTSC, Region::PAL, Region::DENDY] {
+        // The cpu/ppu ratio agrees with the two clock rates
+        let (cpu_cycles, ppu_dots) = region.cpu_ppu_ratio();
+        let ppu_rate = region.cpu_clock_rate() * ppu_dots as f64 / cpu_cycles as f64;
+        assert!(
+            (ppu_rate - region.ppu_clock_rate()).abs() < 2.0,
+            "{} cpu/ppu ratio gives a {} Hz ppu",
+            region.name(),
+            ppu_rate
+        );
+
+        // JMP $8000, nothing reads $2002 so the vblank flag stays up
+        let mut nes = boot_program(&[0x4C, 0x00, 0x80]);
+        nes.set_region(region);
+        let frames = nes.subscribe_frames();
+        run_frame(&mut nes);
+        while nes.cpu.bus.get_ppu().get_scanline() != -1 {
+            nes.clock();
+        }
+
+        // Walk one frame from the pre-render line back to it
+        let mut line = -1;
+        let mut last_line = -1;
+        let mut lines = 0;
+        let mut vblank_line = None;
+        let mut frame_line = None;
+        while frames.try_recv().is_ok() {}
+        loop {
+            let vblank = nes.cpu.bus.peek(0x2002) & 0x80 > 0;
+            nes.clock();
+            let next = nes.cpu.bus.get_ppu().get_scanline();
+            if !vblank && nes.cpu.bus.peek(0x2002) & 0x80 > 0 {
+                vblank_line = Some(next);
+            }
+            if frames.try_recv().is_ok() {
+                frame_line = Some(next);
+            }
+            if next != line {
+                lines = lines + 1;
+                if next == -1 {
+                    break;
+                }
+                last_line = next;
+            }
+            line = next;
+        }
+        assert_eq!(lines, region.scanlines(), "{} scanlines", region.name());
+        assert_eq!(last_line, region.scanlines() - 2, "{} last line", region.name());
+        assert_eq!(vblank_line, Some(region.vblank_line()), "{} vblank line", region.name());
+        assert_eq!(frame_line, vblank_line, "{} frame delivered", region.name());
+    }
+}
+
+// Run a blargg ROM until it reports through $6000, returns the result code
+// and the text it wrote at $6004
+fn run_blargg(nes: &mut Nes) -> (u8, String) {
+    let mut reset_in = 0;
+    for _ in 0..FRAME_LIMIT {
+        run_frame(nes);
+
+        if reset_in > 0 {
+            reset_in = reset_in - 1;
+            if reset_in == 0 {
+                nes.cpu.reset();
+            }
+            continue;
+        }
+
+        // The status byte is only valid once the signature is written
+        let signature = [
+            nes.cpu.read(0x6001, true),
+            nes.cpu.read(0x6002, true),
+            nes.cpu.read(0x6003, true),
+        ];
+        if signature != [0xDE, 0xB0, 0x61] {
+            continue;
+        }
+
+        match nes.cpu.read(0x6000, true) {
+            STATUS_RUNNING => {}
+            STATUS_RESET => {
+                // Reset has to come at least 100ms after the request
+                reset_in = 6;
+            }
+            result => {
+                let mut text = Vec::new();
+                let mut addr = 0x6004;
+                while addr < 0x8000 {
+                    let c = nes.cpu.read(addr, true);
+                    if c == 0 {
+                        break;
+                    }
+                    text.push(c);
+                    addr = addr + 1;
+                }
+                return (result, String::from_utf8_lossy(&text).trim().to_owned());
+            }
+        }
+    }
+    panic!("Test ROM did not finish within {} frames", FRAME_LIMIT);
+}
+
+macro_rules! blargg_test {
+    ($name:ident, $rom:expr) => {
+        #[test]
+        #[ignore = "needs test_roms/"]
+        fn $name() {
+            let mut nes = boot($rom);
+            let (result, text) = run_blargg(&mut nes);
+            assert!(result == 0x00, "{} failed with code {}:\n{}", $rom, result, text);
+        }
+    };
+}
+
+blargg_test!(instr_basics, "instr_test-v5/rom_singles/01-basics.nes");
+blargg_test!(instr_implied, "instr_test-v5/rom_singles/02-implied.nes");
+blargg_test!(instr_immediate, "instr_test-v5/rom_singles/03-immediate.nes");
+blargg_test!(instr_zero_page, "instr_test-v5/rom_singles/04-zero_page.nes");
+blargg_test!(instr_zp_xy, "instr_test-v5/rom_singles/05-zp_xy.nes");
+blargg_test!(instr_absolute, "instr_test-v5/rom_singles/06-absolute.nes");
+blargg_test!(instr_abs_xy, "instr_test-v5/rom_singles/07-abs_xy.nes");
+blargg_test!(instr_ind_x, "instr_test-v5/rom_singles/08-ind_x.nes");
+blargg_test!(instr_ind_y, "instr_test-v5/rom_singles/09-ind_y.nes");
+blargg_test!(instr_branches, "instr_test-v5/rom_singles/10-branches.nes");
+blargg_test!(instr_stack, "instr_test-v5/rom_singles/11-stack.nes");
+blargg_test!(instr_jmp_jsr, "instr_test-v5/rom_singles/12-jmp_jsr.nes");
+blargg_test!(instr_rts, "instr_test-v5/rom_singles/13-rts.nes");
+blargg_test!(instr_rti, "instr_test-v5/rom_singles/14-rti.nes");
+blargg_test!(instr_brk, "instr_test-v5/rom_singles/15-brk.nes");
+blargg_test!(instr_special, "instr_test-v5/rom_singles/16-special.nes");
+
+blargg_test!(instr_misc_abs_x_wrap, "instr_misc/rom_singles/01-abs_x_wrap.nes");
+blargg_test!(instr_misc_branch_wrap, "instr_misc/rom_singles/02-branch_wrap.nes");
+blargg_test!(instr_misc_dummy_reads, "instr_misc/rom_singles/03-dummy_reads.nes");
+blargg_test!(instr_misc_dummy_reads_apu, "instr_misc/rom_singles/04-dummy_reads_apu.nes");
+
+blargg_test!(cpu_interrupts_cli_latency, "cpu_interrupts_v2/rom_singles/1-cli_latency.nes");
+blargg_test!(cpu_interrupts_nmi_and_brk, "cpu_interrupts_v2/rom_singles/2-nmi_and_brk.nes");
+blargg_test!(cpu_interrupts_nmi_and_irq, "cpu_interrupts_v2/rom_singles/3-nmi_and_irq.nes");
+blargg_test!(cpu_interrupts_irq_and_dma, "cpu_interrupts_v2/rom_singles/4-irq_and_dma.nes");
+blargg_test!(cpu_interrupts_branch_delays_irq, "cpu_interrupts_v2/rom_singles/5-branch_delays_irq.nes");
+
+blargg_test!(ppu_vbl_basics, "ppu_vbl_nmi/rom_singles/01-vbl_basics.nes");
+blargg_test!(ppu_vbl_set_time, "ppu_vbl_nmi/rom_singles/02-vbl_set_time.nes");
+blargg_test!(ppu_vbl_clear_time, "ppu_vbl_nmi/rom_singles/03-vbl_clear_time.nes");
+blargg_test!(ppu_nmi_control, "ppu_vbl_nmi/rom_singles/04-nmi_control.nes");
+blargg_test!(ppu_nmi_timing, "ppu_vbl_nmi/rom_singles/05-nmi_timing.nes");
+blargg_test!(ppu_open_bus, "ppu_open_bus/ppu_open_bus.nes");
+
+blargg_test!(apu_len_ctr, "apu_test/rom_singles/1-len_ctr.nes");
+blargg_test!(apu_len_table, "apu_test/rom_singles/2-len_table.nes");
+blargg_test!(apu_irq_flag, "apu_test/rom_singles/3-irq_flag.nes");
+blargg_test!(apu_jitter, "apu_test/rom_singles/4-jitter.nes");
+blargg_test!(apu_len_timing, "apu_test/rom_singles/5-len_timing.nes");
+blargg_test!(apu_irq_flag_timing, "apu_test/rom_singles/6-irq_flag_timing.nes");
+blargg_test!(apu_dmc_basics, "apu_test/rom_singles/7-dmc_basics.nes");
+blargg_test!(apu_dmc_rates, "apu_test/rom_singles/8-dmc_rates.nes");
@@ -0,0 +1,83 @@
+use crate::cpu::Cpu;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+// Writes one line per instruction in the format of nestest.log, e.g.
+// C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+pub struct Tracer {
+    sink: Box<dyn Write + Send>,
+}
+
+impl Tracer {
+    pub fn new(sink: Box<dyn Write + Send>) -> Tracer {
+        return Tracer { sink };
+    }
+
+    pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Tracer> {
+        let file = File::create(path)?;
+        return Ok(Tracer::new(Box::new(BufWriter::new(file))));
+    }
+
+    pub fn to_stdout() -> Tracer {
+        return Tracer::new(Box::new(io::stdout()));
+    }
+
+    /// Log the instruction at pc, called before the cpu fetches it
+    pub fn trace(&mut self, cpu: &mut Cpu) {
+        let line = trace_line(cpu);
+        if let Err(err) = writeln!(self.sink, "{}", line) {
+            eprintln!("Could not write trace: {}", err);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.sink.flush();
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+pub fn trace_line(cpu: &mut Cpu) -> String {
+    let pc = cpu.pc;
+    let instr = cpu.disassemble_instruction(pc, true);
+    let bytes: Vec<String> = instr.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let bytes = bytes.join(" ");
+
+    let mnemonic = instr.name.trim_start_matches('*');
+    let prefix = if instr.name.starts_with('*') { '*' } else { ' ' };
+    let disassembly = if instr.operand.is_empty() {
+        mnemonic.to_owned()
+    } else {
+        format!("{} {}", mnemonic, instr.operand)
+    };
+
+    // nestest numbers the pre-render line 261 rather than -1
+    let ppu = cpu.bus.get_ppu();
+    let mut scanline = ppu.get_scanline();
+    if scanline < 0 {
+        scanline = ppu.region().scanlines() - 1;
+    }
+    let dot = ppu.get_cycle();
+
+    return format!(
+        "{:04X}  {:<9}{}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        pc,
+        bytes,
+        prefix,
+        disassembly,
+        cpu.a,
+        cpu.x,
+        cpu.y,
+        cpu.status,
+        cpu.stkp,
+        scanline,
+        dot,
+        cpu.clock_count
+    );
+}